@@ -0,0 +1,92 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, LitStr, parse_macro_input};
+
+/// Implements `CoClass` from `#[clsid("...")]`, `#[version_independent_prog_id("...")]` and an
+/// optional `#[prog_id("...")]`. The CLSID is parsed at compile time, with or without braces.
+#[proc_macro_derive(CoClass, attributes(clsid, prog_id, version_independent_prog_id))]
+pub fn derive_co_class(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut clsid = None;
+    let mut prog_id = None;
+    let mut version_independent_prog_id = None;
+
+    for attr in &input.attrs {
+        let slot = if attr.path().is_ident("clsid") {
+            &mut clsid
+        } else if attr.path().is_ident("prog_id") {
+            &mut prog_id
+        } else if attr.path().is_ident("version_independent_prog_id") {
+            &mut version_independent_prog_id
+        } else {
+            continue;
+        };
+
+        if slot.is_some() {
+            return Err(syn::Error::new_spanned(attr, "duplicate attribute"));
+        }
+
+        *slot = Some(attr.parse_args::<LitStr>()?);
+    }
+
+    let missing = |name: &str| {
+        syn::Error::new_spanned(
+            &input.ident,
+            format!("deriving CoClass requires a #[{name}(\"...\")] attribute"),
+        )
+    };
+
+    let clsid = clsid.ok_or_else(|| missing("clsid"))?;
+    let clsid = parse_guid(&clsid.value()).ok_or_else(|| {
+        syn::Error::new_spanned(
+            &clsid,
+            "malformed CLSID, expected {xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}",
+        )
+    })?;
+    let version_independent_prog_id =
+        version_independent_prog_id.ok_or_else(|| missing("version_independent_prog_id"))?;
+    let prog_id = match prog_id {
+        Some(prog_id) => quote!(::registry_classes_utils::__private::w!(#prog_id)),
+        None => quote!(::registry_classes_utils::__private::PCWSTR::null()),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::registry_classes_utils::com::CoClass for #name #type_generics #where_clause {
+            const CLSID: ::registry_classes_utils::__private::GUID =
+                ::registry_classes_utils::__private::GUID::from_u128(#clsid);
+            const PROG_ID: ::registry_classes_utils::__private::PCWSTR = #prog_id;
+            const VERSION_INDEPENDENT_PROG_ID: ::registry_classes_utils::__private::PCWSTR =
+                ::registry_classes_utils::__private::w!(#version_independent_prog_id);
+        }
+    })
+}
+
+fn parse_guid(value: &str) -> Option<u128> {
+    let value = value
+        .strip_prefix('{')
+        .and_then(|value| value.strip_suffix('}'))
+        .unwrap_or(value);
+
+    let groups = value.split('-').collect::<Vec<_>>();
+
+    if groups.iter().map(|group| group.len()).ne([8, 4, 4, 4, 12])
+        || !groups
+            .iter()
+            .all(|group| group.bytes().all(|c| c.is_ascii_hexdigit()))
+    {
+        return None;
+    }
+
+    u128::from_str_radix(&groups.concat(), 16).ok()
+}
@@ -1,13 +1,56 @@
 use std::ffi::c_void;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::marker::PhantomData;
+use std::sync::{
+    PoisonError, RwLock,
+    atomic::{AtomicUsize, Ordering},
+};
 
-use windows::Win32::Foundation::{CLASS_E_NOAGGREGATION, E_POINTER};
+use windows::Win32::Foundation::{
+    CLASS_E_NOAGGREGATION, CLASS_E_NOTLICENSED, E_NOTIMPL, E_POINTER,
+};
 use windows::{
-    Win32::System::Com::{IClassFactory, IClassFactory_Impl},
-    core::{BOOL, GUID, Ref, implement},
+    Win32::System::{
+        Com::{IClassFactory, IClassFactory_Impl},
+        Ole::{IClassFactory2, IClassFactory2_Impl, LICINFO},
+    },
+    core::{BOOL, BSTR, GUID, IUnknown, Interface, Ref, implement},
 };
 
+use crate::com::CreatableCoClass;
+
 static LOCK_COUNT: AtomicUsize = AtomicUsize::new(0);
+static OBJECT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps the module loaded while it is alive. Embedding one in every `#[implement]`ed object
+/// handed out by a [`ClassFactory`] ties the objects' lifetimes into
+/// [`ClassFactory::can_unload_now`].
+#[derive(Debug)]
+pub struct CountedObject(());
+
+impl CountedObject {
+    pub fn new() -> Self {
+        OBJECT_COUNT.fetch_add(1, Ordering::AcqRel);
+        Self(())
+    }
+}
+
+impl Default for CountedObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for CountedObject {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for CountedObject {
+    fn drop(&mut self) {
+        OBJECT_COUNT.fetch_sub(1, Ordering::AcqRel);
+    }
+}
 
 #[implement(IClassFactory)]
 pub struct ClassFactory {
@@ -22,62 +65,262 @@ impl ClassFactory {
     }
 
     pub fn can_unload_now() -> bool {
-        LOCK_COUNT.load(Ordering::Acquire) == 0
+        LOCK_COUNT.load(Ordering::Acquire) == 0 && OBJECT_COUNT.load(Ordering::Acquire) == 0
+    }
+}
+
+type ActivationLogger = Box<dyn Fn(&GUID, &windows::core::Result<()>) + Send + Sync>;
+
+static ACTIVATION_LOGGER: RwLock<Option<ActivationLogger>> = RwLock::new(None);
+
+/// Installs a hook that is invoked with the requested IID and the outcome of every
+/// `IClassFactory::CreateInstance` call, replacing any previously installed hook.
+pub fn set_activation_logger(
+    logger: impl Fn(&GUID, &windows::core::Result<()>) + Send + Sync + 'static,
+) {
+    *ACTIVATION_LOGGER
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(Box::new(logger));
+}
+
+/// Validates the arguments shared by every `CreateInstance` implementation, runs `constructor`
+/// and reports the outcome to the activation logger. An aggregated object may only be asked for
+/// `IUnknown`, so `constructor` is only passed an outer unknown together with that IID.
+fn create_instance(
+    outer: Ref<'_, IUnknown>,
+    iid: *const GUID,
+    ppv: *mut *mut c_void,
+    constructor: impl FnOnce(
+        Option<IUnknown>,
+        *const GUID,
+        *mut *mut c_void,
+    ) -> windows::core::Result<()>,
+) -> windows::core::Result<()> {
+    let result = if iid.is_null() || ppv.is_null() {
+        Err(E_POINTER.into())
+    } else if outer.is_some() && unsafe { *iid } != IUnknown::IID {
+        Err(CLASS_E_NOAGGREGATION.into())
+    } else {
+        constructor(outer.cloned(), iid, ppv)
+    };
+
+    if !iid.is_null()
+        && let Some(logger) = &*ACTIVATION_LOGGER
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    {
+        logger(unsafe { &*iid }, &result);
     }
+
+    result
+}
+
+fn lock_server(flock: BOOL) -> windows::core::Result<()> {
+    if flock.as_bool() {
+        LOCK_COUNT.fetch_add(1, Ordering::AcqRel);
+    } else {
+        // An unbalanced unlock must not wrap the count around and pin the module forever.
+        let _ = LOCK_COUNT.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+            count.checked_sub(1)
+        });
+    }
+
+    Ok(())
 }
 
 impl IClassFactory_Impl for ClassFactory_Impl {
     #[allow(clippy::not_unsafe_ptr_arg_deref)]
     fn CreateInstance(
         &self,
-        outer: Ref<'_, windows::core::IUnknown>,
+        outer: Ref<'_, IUnknown>,
         iid: *const GUID,
-        ppv: *mut *mut core::ffi::c_void,
+        ppv: *mut *mut c_void,
     ) -> windows::core::Result<()> {
-        if outer.is_some() {
-            return Err(CLASS_E_NOAGGREGATION.into());
-        }
+        create_instance(outer, iid, ppv, |outer, iid, ppv| match outer {
+            Some(_) => Err(CLASS_E_NOAGGREGATION.into()),
+            None => (self.constructor)(iid, ppv),
+        })
+    }
 
-        if iid.is_null() {
-            return Err(E_POINTER.into());
+    fn LockServer(&self, flock: BOOL) -> windows::core::Result<()> {
+        lock_server(flock)
+    }
+}
+
+/// A class factory for a single class known at compile time. Use [`ClassFactory`] when the
+/// constructor is only chosen at runtime.
+#[implement(IClassFactory)]
+pub struct CoClassFactory<T>
+where
+    T: CreatableCoClass + Into<IUnknown> + 'static,
+{
+    _class: PhantomData<fn() -> T>,
+}
+
+impl<T: CreatableCoClass + Into<IUnknown> + 'static> CoClassFactory<T> {
+    pub fn new() -> Self {
+        Self {
+            _class: PhantomData,
         }
+    }
+}
 
-        if ppv.is_null() {
-            return Err(E_POINTER.into());
+impl<T: CreatableCoClass + Into<IUnknown> + 'static> Default for CoClassFactory<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: CreatableCoClass + Into<IUnknown> + 'static> IClassFactory_Impl for CoClassFactory_Impl<T> {
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn CreateInstance(
+        &self,
+        outer: Ref<'_, IUnknown>,
+        iid: *const GUID,
+        ppv: *mut *mut c_void,
+    ) -> windows::core::Result<()> {
+        create_instance(outer, iid, ppv, construct::<T>)
+    }
+
+    fn LockServer(&self, flock: BOOL) -> windows::core::Result<()> {
+        lock_server(flock)
+    }
+}
+
+fn construct<T: CreatableCoClass + Into<IUnknown>>(
+    outer: Option<IUnknown>,
+    iid: *const GUID,
+    ppv: *mut *mut c_void,
+) -> windows::core::Result<()> {
+    let object = match outer {
+        Some(outer) => T::new_aggregated(outer)?,
+        None => T::new()?,
+    };
+
+    unsafe { object.into().query(iid, ppv).ok() }
+}
+
+/// Decides whether a [`LicensedClassFactory`] may create objects.
+pub trait LicenseProvider {
+    /// Returns whether this machine is licensed, so objects can be created without a key.
+    fn is_licensed(&self) -> bool;
+    /// Returns the key handed to hosts that embed the class on unlicensed machines, if any.
+    fn runtime_key(&self) -> Option<BSTR>;
+    fn validate_key(&self, key: &BSTR) -> bool;
+}
+
+/// A class factory implementing `IClassFactory2`, which only creates objects on licensed
+/// machines or for callers presenting a key accepted by `L`.
+#[implement(IClassFactory2)]
+pub struct LicensedClassFactory<T, L>
+where
+    T: CreatableCoClass + Into<IUnknown> + 'static,
+    L: LicenseProvider + 'static,
+{
+    license: L,
+    _class: PhantomData<fn() -> T>,
+}
+
+impl<T: CreatableCoClass + Into<IUnknown> + 'static, L: LicenseProvider + 'static>
+    LicensedClassFactory<T, L>
+{
+    pub fn new(license: L) -> Self {
+        Self {
+            license,
+            _class: PhantomData,
         }
+    }
+}
+
+impl<T: CreatableCoClass + Into<IUnknown> + 'static, L: LicenseProvider + 'static>
+    IClassFactory_Impl for LicensedClassFactory_Impl<T, L>
+{
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn CreateInstance(
+        &self,
+        outer: Ref<'_, IUnknown>,
+        iid: *const GUID,
+        ppv: *mut *mut c_void,
+    ) -> windows::core::Result<()> {
+        create_instance(outer, iid, ppv, |outer, iid, ppv| {
+            if !self.license.is_licensed() {
+                return Err(CLASS_E_NOTLICENSED.into());
+            }
 
-        (self.constructor)(iid, ppv)
+            construct::<T>(outer, iid, ppv)
+        })
     }
 
     fn LockServer(&self, flock: BOOL) -> windows::core::Result<()> {
-        if flock.as_bool() {
-            LOCK_COUNT.fetch_add(1, Ordering::AcqRel);
-        } else {
-            LOCK_COUNT.fetch_sub(1, Ordering::AcqRel);
+        lock_server(flock)
+    }
+}
+
+impl<T: CreatableCoClass + Into<IUnknown> + 'static, L: LicenseProvider + 'static>
+    IClassFactory2_Impl for LicensedClassFactory_Impl<T, L>
+{
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn GetLicInfo(&self, plicinfo: *mut LICINFO) -> windows::core::Result<()> {
+        if plicinfo.is_null() {
+            return Err(E_POINTER.into());
+        }
+
+        unsafe {
+            plicinfo.write(LICINFO {
+                cbLicInfo: size_of::<LICINFO>() as i32,
+                fRuntimeKeyAvail: self.license.runtime_key().is_some().into(),
+                fLicVerified: self.license.is_licensed().into(),
+            });
         }
 
         Ok(())
     }
+
+    fn RequestLicKey(&self, _dwreserved: u32) -> windows::core::Result<BSTR> {
+        // Only licensed machines may hand out keys to embed elsewhere.
+        if !self.license.is_licensed() {
+            return Err(CLASS_E_NOTLICENSED.into());
+        }
+
+        self.license.runtime_key().ok_or_else(|| E_NOTIMPL.into())
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn CreateInstanceLic(
+        &self,
+        outer: Ref<'_, IUnknown>,
+        _reserved: Ref<'_, IUnknown>,
+        iid: *const GUID,
+        key: &BSTR,
+        ppv: *mut *mut c_void,
+    ) -> windows::core::Result<()> {
+        create_instance(outer, iid, ppv, |outer, iid, ppv| {
+            if !self.license.validate_key(key) {
+                return Err(CLASS_E_NOTLICENSED.into());
+            }
+
+            construct::<T>(outer, iid, ppv)
+        })
+    }
 }
 
 #[macro_export]
 macro_rules! dll_get_class_object_impl {
     (clsid = $clsid:ident, iid = $iid:ident, ppv = $ppv:ident, classes = [ $($class:ident),+ ] ) => {{
         fn __dll_get_class_object_impl(
-            clsid: *const GUID,
-            iid: *const GUID,
-            ppv: *mut *mut c_void,
-        ) -> HRESULT {
-            use windows::core::{ComObject, Interface, IUnknown};
-            use windows::Win32::Foundation::{CLASS_E_CLASSNOTAVAILABLE, E_POINTER};
-            use $crate::class_factory::ClassFactory;
-            use $crate::com::{CoClass, CreatableCoClass};
+            clsid: *const $crate::__private::GUID,
+            iid: *const $crate::__private::GUID,
+            ppv: *mut *mut $crate::__private::c_void,
+        ) -> $crate::__private::HRESULT {
+            use $crate::__private::{CLASS_E_CLASSNOTAVAILABLE, E_POINTER, IUnknown, Interface};
+            use $crate::class_factory::CoClassFactory;
+            use $crate::com::CoClass;
 
             if ppv.is_null() {
                 return E_POINTER;
             } else {
                 unsafe {
-                    ppv.write(std::ptr::null_mut());
+                    ppv.write(::std::ptr::null_mut());
                 }
             }
 
@@ -89,23 +332,176 @@ macro_rules! dll_get_class_object_impl {
                 return E_POINTER;
             }
 
-            let class_factory = match unsafe { *clsid } {
-                $($class::CLSID => ClassFactory::new(|iid, ppv| unsafe {
-                    ComObject::new($class::new()?)
-                    .as_interface::<IUnknown>()
-                    .query(iid, ppv).ok()
-                }),)+
+            let class_factory: IUnknown = match unsafe { *clsid } {
+                $($class::CLSID => CoClassFactory::<$class>::new().into(),)+
                 _ => return CLASS_E_CLASSNOTAVAILABLE,
             };
 
             #[allow(unreachable_code)]
             unsafe {
-                ComObject::new(class_factory)
-                    .as_interface::<IUnknown>()
-                    .query(iid, ppv)
+                class_factory.query(iid, ppv)
             }
         }
 
         __dll_get_class_object_impl($clsid, $iid, $ppv)
     }};
 }
+
+/// Registers `classes` as in-process servers in a single transaction, returning the outcome as
+/// an `HRESULT` for `DllRegisterServer`. `scope` defaults to
+/// [`RegistrationScope::PerMachine`](crate::registry::RegistrationScope::PerMachine).
+#[macro_export]
+macro_rules! dll_register_server_impl {
+    (module_path = $module_path:expr, threading_model = $threading_model:expr, classes = [ $($class:ident),+ ] ) => {
+        $crate::dll_register_server_impl!(
+            scope = $crate::registry::RegistrationScope::PerMachine,
+            module_path = $module_path,
+            threading_model = $threading_model,
+            classes = [ $($class),+ ]
+        )
+    };
+    (scope = $scope:expr, module_path = $module_path:expr, threading_model = $threading_model:expr, classes = [ $($class:ident),+ ] ) => {{
+        fn __dll_register_server_impl(
+            scope: $crate::registry::RegistrationScope,
+            module_path: $crate::registry::NullTerminatedSlice,
+            threading_model: $crate::registry::ApartmentType,
+        ) -> $crate::__private::Result<()> {
+            use $crate::__private::PCWSTR;
+            use $crate::registry::{
+                register_com_extension, transaction::{Key, Transaction}, ServerKind,
+            };
+
+            let transaction = Transaction::new(PCWSTR::null(), false)?;
+            let classes = Key::classes_root(&transaction, scope)?;
+
+            $(register_com_extension::<$class>(
+                &classes,
+                ServerKind::Inproc {
+                    module_path,
+                    apartment_type: threading_model,
+                },
+                PCWSTR::null(),
+                PCWSTR::null(),
+            )?;)+
+
+            drop(classes);
+            transaction.commit()
+        }
+
+        match __dll_register_server_impl($scope, $module_path, $threading_model) {
+            Ok(()) => $crate::__private::S_OK,
+            Err(e) => e.code(),
+        }
+    }};
+}
+
+/// Unregisters `classes` in a single transaction, returning the outcome as an `HRESULT` for
+/// `DllUnregisterServer`. `scope` defaults to
+/// [`RegistrationScope::PerMachine`](crate::registry::RegistrationScope::PerMachine).
+#[macro_export]
+macro_rules! dll_unregister_server_impl {
+    (classes = [ $($class:ident),+ ] ) => {
+        $crate::dll_unregister_server_impl!(
+            scope = $crate::registry::RegistrationScope::PerMachine,
+            classes = [ $($class),+ ]
+        )
+    };
+    (scope = $scope:expr, classes = [ $($class:ident),+ ] ) => {{
+        fn __dll_unregister_server_impl(
+            scope: $crate::registry::RegistrationScope,
+        ) -> $crate::__private::Result<()> {
+            use $crate::__private::PCWSTR;
+            use $crate::registry::{
+                transaction::{Key, Transaction}, unregister_com_extension,
+            };
+
+            let transaction = Transaction::new(PCWSTR::null(), false)?;
+            let classes = Key::classes_root(&transaction, scope)?;
+
+            $(unregister_com_extension::<$class>(&classes)?;)+
+
+            drop(classes);
+            transaction.commit()
+        }
+
+        match __dll_unregister_server_impl($scope) {
+            Ok(()) => $crate::__private::S_OK,
+            Err(e) => e.code(),
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! dll_can_unload_now_impl {
+    () => {
+        if $crate::class_factory::ClassFactory::can_unload_now() {
+            $crate::__private::S_OK
+        } else {
+            $crate::__private::S_FALSE
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::{S_FALSE, S_OK};
+
+    use super::*;
+
+    #[test]
+    fn lock_server_stays_balanced_across_threads() {
+        const THREADS: usize = 8;
+        const ITERATIONS: usize = 10_000;
+
+        std::thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| {
+                    for _ in 0..ITERATIONS {
+                        lock_server(true.into()).unwrap();
+
+                        // This thread's own lock is still held.
+                        assert_eq!(crate::dll_can_unload_now_impl!(), S_FALSE);
+
+                        lock_server(false.into()).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(LOCK_COUNT.load(Ordering::Acquire), 0);
+        assert_eq!(crate::dll_can_unload_now_impl!(), S_OK);
+
+        // An unbalanced unlock leaves the count at zero instead of wrapping around.
+        lock_server(false.into()).unwrap();
+        assert_eq!(LOCK_COUNT.load(Ordering::Acquire), 0);
+        assert!(ClassFactory::can_unload_now());
+    }
+
+    #[test]
+    fn aggregation_requires_iunknown() {
+        let outer: IUnknown = ClassFactory::new(|_, _| Err(E_NOTIMPL.into())).into();
+        let outer = Some(outer);
+        let mut object = std::ptr::null_mut();
+
+        let result = create_instance(
+            Ref::from(&outer),
+            &IClassFactory::IID,
+            &mut object,
+            |_, _, _| panic!("constructor called for an aggregated non-IUnknown request"),
+        );
+        assert_eq!(result.unwrap_err().code(), CLASS_E_NOAGGREGATION);
+
+        let mut constructed = false;
+        create_instance(
+            Ref::from(&outer),
+            &IUnknown::IID,
+            &mut object,
+            |inner, _, _| {
+                constructed = inner.is_some();
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(constructed);
+    }
+}
@@ -1,23 +1,65 @@
 use std::{
+    collections::HashMap,
     fmt::Display,
     io::{Cursor, Write},
 };
 
-use windows::core::{GUID, PCWSTR};
+use windows::{
+    Win32::Foundation::{CLASS_E_NOAGGREGATION, E_INVALIDARG},
+    core::{GUID, IUnknown, PCWSTR},
+};
+
+use crate::registry::AppIdOptions;
 
 pub trait CoClass {
     const CLSID: GUID;
+    /// The versioned ProgId, or null if the class is only activated by CLSID.
     const PROG_ID: PCWSTR;
+    /// The ProgId that stays the same across versions. Unlike [`CoClass::PROG_ID`], this is
+    /// required: registration fails with `E_INVALIDARG` if it is null.
     const VERSION_INDEPENDENT_PROG_ID: PCWSTR;
+    /// The type library describing the class, registered as `CLSID\{clsid}\TypeLib`.
+    const TYPE_LIB: Option<GUID> = None;
+    /// Component categories registered as `CLSID\{clsid}\Implemented Categories\{catid}`.
+    const IMPLEMENTED_CATEGORIES: &'static [GUID] = &[];
+    /// The index of the icon in the server module, registered as `{ProgId}\DefaultIcon`. The
+    /// module path is only known at registration time, so only the index is given here.
+    const DEFAULT_ICON: Option<i32> = None;
+    /// The AppID the class is hosted under, registered as `CLSID\{clsid}\AppID` and
+    /// `AppID\{appid}` with [`CoClass::APP_ID_OPTIONS`].
+    const APP_ID: Option<GUID> = None;
+    const APP_ID_OPTIONS: AppIdOptions = AppIdOptions::new();
 }
 
 pub trait CreatableCoClass: CoClass + Sized {
+    /// Creates the object handed out by the class factory. The object should hold a
+    /// [`CountedObject`](crate::class_factory::CountedObject) so the module is not unloaded
+    /// while it is alive.
     fn new() -> windows::core::Result<Self>;
+
+    /// Creates the object as the inner object of an aggregate controlled by `outer`.
+    ///
+    /// The factory only calls this when the caller asked for `IUnknown`, and hands `outer` the
+    /// `IUnknown` of the returned object. That unknown must be the inner object's own,
+    /// non-delegating one: its `QueryInterface`, `AddRef` and `Release` act on the inner object
+    /// alone. Every other interface the object exposes must instead forward those three calls to
+    /// `outer`. Objects built with `#[implement]` answer all of their interfaces through the same
+    /// non-delegating unknown, so they cannot be aggregated and should keep this default, which
+    /// fails with `CLASS_E_NOAGGREGATION`.
+    fn new_aggregated(outer: IUnknown) -> windows::core::Result<Self> {
+        let _ = outer;
+        Err(CLASS_E_NOAGGREGATION.into())
+    }
 }
 
-struct GuidWrapper<'a>(&'a GUID);
+/// A table keyed by CLSID or IID. `GUID` hashes and compares over all of its fields, so no
+/// wrapper is needed.
+pub type GuidMap<V> = HashMap<GUID, V>;
 
-impl Display for GuidWrapper<'_> {
+/// Formats a GUID in the braced registry form, `{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}`.
+pub struct GuidDisplay<'a>(pub &'a GUID);
+
+impl Display for GuidDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
@@ -42,13 +84,238 @@ pub trait GuidExt {
     fn to_wide(&self) -> [u16; 39] {
         self.to_ascii_with_nul().map(|value| value as u16)
     }
+    /// Formats without the surrounding braces, still nul-terminated.
+    fn to_ascii_no_braces(&self) -> [u8; 37] {
+        let mut result = [0u8; 37];
+        result[..36].copy_from_slice(&self.to_ascii_with_nul()[1..37]);
+        result
+    }
+    fn to_wide_no_braces(&self) -> [u16; 37] {
+        self.to_ascii_no_braces().map(|value| value as u16)
+    }
+    /// Returns whether this is the all-zero GUID, which some tools write as "unregistered".
+    fn is_zero(&self) -> bool;
+    /// Formats as 32 contiguous hex digits, without braces or hyphens.
+    fn to_hex32(&self) -> [u16; 32];
+    /// Parses the form written by [`GuidExt::to_hex32`].
+    fn from_hex32(value: &str) -> windows::core::Result<Self>
+    where
+        Self: Sized;
+    /// Parses the braced registry form, `{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}`.
+    fn from_str(value: &str) -> windows::core::Result<Self>
+    where
+        Self: Sized;
+    /// Parses the form written by [`GuidExt::to_wide`], with or without the trailing nul.
+    fn from_wide(value: &[u16]) -> windows::core::Result<Self>
+    where
+        Self: Sized,
+    {
+        let value = value.strip_suffix(&[0]).unwrap_or(value);
+        Self::from_str(&String::from_utf16(value).map_err(|_| E_INVALIDARG)?)
+    }
 }
 
 impl GuidExt for GUID {
     fn to_ascii_with_nul(&self) -> [u8; 39] {
         let mut cursor = Cursor::new([0u8; 39]);
-        write!(cursor, "{}", GuidWrapper(self)).unwrap();
+        write!(cursor, "{}", GuidDisplay(self)).unwrap();
         assert!(cursor.position() == 38);
         cursor.into_inner()
     }
+
+    fn is_zero(&self) -> bool {
+        *self == GUID::zeroed()
+    }
+
+    fn to_hex32(&self) -> [u16; 32] {
+        let mut result = [0u16; 32];
+
+        for (c, digit) in result
+            .iter_mut()
+            .zip(format!("{:032x}", self.to_u128()).bytes())
+        {
+            *c = digit.into();
+        }
+
+        result
+    }
+
+    fn from_hex32(value: &str) -> windows::core::Result<Self> {
+        if value.len() != 32 || !value.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(E_INVALIDARG.into());
+        }
+
+        u128::from_str_radix(value, 16)
+            .map(GUID::from_u128)
+            .map_err(|_| E_INVALIDARG.into())
+    }
+
+    fn from_str(value: &str) -> windows::core::Result<Self> {
+        value
+            .strip_prefix('{')
+            .and_then(|value| value.strip_suffix('}'))
+            .ok_or_else(|| windows::core::Error::from(E_INVALIDARG))
+            .and_then(GUID::try_from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, RandomState};
+
+    use windows::core::Interface;
+
+    use super::*;
+
+    const GUID_A: GUID = GUID::from_u128(0x3f2c1d8e_5a47_4b6e_9c0d_1e2f3a4b5c6d);
+
+    #[test]
+    fn equal_guids_share_a_map_entry() {
+        let same = GUID::from_values(
+            0x3f2c1d8e,
+            0x5a47,
+            0x4b6e,
+            [0x9c, 0x0d, 0x1e, 0x2f, 0x3a, 0x4b, 0x5c, 0x6d],
+        );
+        let state = RandomState::new();
+
+        assert_eq!(GUID_A, same);
+        assert_eq!(state.hash_one(GUID_A), state.hash_one(same));
+
+        let mut map = GuidMap::new();
+        map.insert(GUID_A, "first");
+        assert_eq!(map.get(&same), Some(&"first"));
+
+        map.insert(same, "second");
+        assert_eq!(map.len(), 1);
+        assert_eq!(map[&GUID_A], "second");
+        assert_eq!(map.get(&IUnknown::IID), None);
+    }
+
+    #[test]
+    fn hex32_round_trips_known_guids() {
+        for (guid, hex) in [
+            (GUID_A, "3f2c1d8e5a474b6e9c0d1e2f3a4b5c6d"),
+            (IUnknown::IID, "0000000000000000c000000000000046"),
+            (GUID::zeroed(), "00000000000000000000000000000000"),
+        ] {
+            assert_eq!(String::from_utf16(&guid.to_hex32()).unwrap(), hex);
+            assert_eq!(GUID::from_hex32(hex).unwrap(), guid);
+            assert_eq!(GUID::from_hex32(&hex.to_uppercase()).unwrap(), guid);
+        }
+    }
+
+    #[test]
+    fn from_hex32_rejects_other_forms() {
+        for value in [
+            "",
+            "3f2c1d8e5a474b6e9c0d1e2f3a4b5c6",
+            "3f2c1d8e5a474b6e9c0d1e2f3a4b5c6d0",
+            "3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d",
+            "{3f2c1d8e5a474b6e9c0d1e2f3a4b5c6d}",
+            "+f2c1d8e5a474b6e9c0d1e2f3a4b5c6d",
+            "3f2c1d8e5a474b6e9c0d1e2f3a4b5c6g",
+        ] {
+            assert_eq!(GUID::from_hex32(value).unwrap_err().code(), E_INVALIDARG);
+        }
+    }
+
+    #[test]
+    fn from_str_parses_braced_guids() {
+        for value in [
+            "{3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d}",
+            "{3F2C1D8E-5A47-4B6E-9C0D-1E2F3A4B5C6D}",
+            "{3f2c1d8e-5A47-4b6e-9C0D-1e2f3a4b5c6d}",
+        ] {
+            assert_eq!(GUID::from_str(value).unwrap(), GUID_A);
+        }
+    }
+
+    #[test]
+    fn from_str_rejects_other_forms() {
+        for value in [
+            "",
+            "{}",
+            "3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d",
+            "{3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d",
+            "3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d}",
+            "{3f2c1d8e5a474b6e9c0d1e2f3a4b5c6d}",
+            "{3f2c1d8e-5a47-4b6e-9c0d1e2f3a4b5c6d0}",
+            "{3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6g}",
+            "{{3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d}}",
+            " {3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d}",
+        ] {
+            assert_eq!(
+                GUID::from_str(value).unwrap_err().code(),
+                E_INVALIDARG,
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn from_wide_accepts_optional_nul() {
+        let wide = GUID_A.to_wide();
+
+        assert_eq!(GUID::from_wide(&wide).unwrap(), GUID_A);
+        assert_eq!(GUID::from_wide(&wide[..38]).unwrap(), GUID_A);
+        assert_eq!(
+            GUID::from_wide(&wide[..37]).unwrap_err().code(),
+            E_INVALIDARG
+        );
+        assert_eq!(GUID::from_wide(&[0xd800]).unwrap_err().code(), E_INVALIDARG);
+    }
+
+    #[test]
+    fn to_wide_round_trips_through_from_wide() {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            state
+        };
+
+        for _ in 0..1000 {
+            let guid = GUID::from_u128(((next() as u128) << 64) | next() as u128);
+
+            assert_eq!(GUID::from_wide(&guid.to_wide()).unwrap(), guid);
+            assert_eq!(
+                GUID::from_str(&GuidDisplay(&guid).to_string()).unwrap(),
+                guid
+            );
+        }
+    }
+
+    #[test]
+    fn braced_and_unbraced_forms_agree() {
+        let braced = "{3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d}";
+
+        assert_eq!(GuidDisplay(&GUID_A).to_string(), braced);
+        assert_eq!(&GUID_A.to_ascii_with_nul()[..38], braced.as_bytes());
+        assert_eq!(GUID_A.to_ascii_with_nul()[38], 0);
+
+        let ascii = GUID_A.to_ascii_no_braces();
+        assert_eq!(&ascii[..36], &braced.as_bytes()[1..37]);
+        assert_eq!(ascii[36], 0);
+
+        let wide = GUID_A.to_wide();
+        assert_eq!(wide[..38], braced.encode_utf16().collect::<Vec<_>>());
+        assert_eq!(GUID_A.to_wide_no_braces()[..36], wide[1..37]);
+        assert_eq!(GUID_A.to_wide_no_braces()[36], 0);
+    }
+
+    #[test]
+    fn display_is_lowercase_and_zero_padded() {
+        let guid = GUID::from_values(0xab, 0xc, 0xd, [0xe, 0xf, 0, 1, 2, 3, 4, 0xa5]);
+
+        assert_eq!(
+            GuidDisplay(&guid).to_string(),
+            "{000000ab-000c-000d-0e0f-0001020304a5}"
+        );
+        assert_eq!(
+            GuidDisplay(&GUID::zeroed()).to_string(),
+            "{00000000-0000-0000-0000-000000000000}"
+        );
+    }
 }
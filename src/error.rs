@@ -0,0 +1,85 @@
+use std::fmt::Display;
+
+use windows::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, ERROR_RM_NOT_ACTIVE, ERROR_TRANSACTIONAL_CONFLICT,
+    ERROR_TRANSACTIONS_UNSUPPORTED_REMOTE, WIN32_ERROR,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryError {
+    /// The Kernel Transaction Manager is unavailable for this volume or system, so callers
+    /// should fall back to a non-transacted path.
+    TransactionsUnsupported(windows::core::Error),
+    /// The key to open does not exist. Opening never creates keys.
+    NotFound(windows::core::Error),
+    /// Writing per-machine registrations usually needs elevation.
+    AccessDenied(windows::core::Error),
+    /// Another transaction has modified the same key, so this one should be retried.
+    TransactionConflict(windows::core::Error),
+    Other(windows::core::Error),
+}
+
+impl RegistryError {
+    pub fn as_windows_error(&self) -> &windows::core::Error {
+        match self {
+            Self::TransactionsUnsupported(e)
+            | Self::NotFound(e)
+            | Self::AccessDenied(e)
+            | Self::TransactionConflict(e)
+            | Self::Other(e) => e,
+        }
+    }
+}
+
+impl From<windows::core::Error> for RegistryError {
+    fn from(value: windows::core::Error) -> Self {
+        match WIN32_ERROR::from_error(&value) {
+            Some(ERROR_RM_NOT_ACTIVE | ERROR_TRANSACTIONS_UNSUPPORTED_REMOTE) => {
+                Self::TransactionsUnsupported(value)
+            }
+            Some(ERROR_FILE_NOT_FOUND) => Self::NotFound(value),
+            Some(ERROR_ACCESS_DENIED) => Self::AccessDenied(value),
+            Some(ERROR_TRANSACTIONAL_CONFLICT) => Self::TransactionConflict(value),
+            _ => Self::Other(value),
+        }
+    }
+}
+
+/// Classifies `value` without taking it, for callers that still need the original error.
+impl From<&windows::core::Error> for RegistryError {
+    fn from(value: &windows::core::Error) -> Self {
+        value.clone().into()
+    }
+}
+
+impl From<RegistryError> for windows::core::Error {
+    fn from(value: RegistryError) -> Self {
+        match value {
+            RegistryError::TransactionsUnsupported(e)
+            | RegistryError::NotFound(e)
+            | RegistryError::AccessDenied(e)
+            | RegistryError::TransactionConflict(e)
+            | RegistryError::Other(e) => e,
+        }
+    }
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TransactionsUnsupported(e) => {
+                write!(f, "registry transactions are not supported: {e}")
+            }
+            Self::TransactionConflict(e) => {
+                write!(f, "conflicting change in another transaction: {e}")
+            }
+            Self::NotFound(e) | Self::AccessDenied(e) | Self::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.as_windows_error())
+    }
+}
@@ -1,4 +1,18 @@
 pub mod class_factory;
 pub mod com;
+pub mod error;
 pub mod registry;
 pub mod util;
+
+#[cfg(feature = "derive")]
+pub use registry_classes_utils_derive::CoClass;
+
+/// Paths used by the derive macro and the `dll_*_impl!` macros, so crates using them do not need
+/// their own `windows` dependency.
+#[doc(hidden)]
+pub mod __private {
+    pub use std::ffi::c_void;
+
+    pub use windows::Win32::Foundation::{CLASS_E_CLASSNOTAVAILABLE, E_POINTER, S_FALSE, S_OK};
+    pub use windows_core::{GUID, HRESULT, IUnknown, Interface, PCWSTR, Result, w};
+}
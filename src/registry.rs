@@ -1,81 +1,625 @@
-use std::ops::Deref;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    marker::PhantomData,
+    ops::Deref,
+    os::windows::ffi::OsStringExt,
+    path::Path,
+    rc::Rc,
+};
 
-use transaction::Key;
-use windows::core::{PCWSTR, w};
+use transaction::{Key, Transaction};
+use windows::{
+    Win32::{
+        Foundation::E_INVALIDARG,
+        System::{
+            Com::IClassFactory,
+            Registry::{
+                HKEY, HKEY_LOCAL_MACHINE, HKEY_USERS, KEY_WOW64_32KEY, KEY_WOW64_64KEY,
+                REG_SAM_FLAGS,
+            },
+        },
+    },
+    core::{GUID, IUnknown, PCWSTR, w},
+};
 
-use crate::com::{CoClass, GuidExt};
+use crate::{
+    class_factory::CoClassFactory,
+    com::{CoClass, CreatableCoClass, GuidExt},
+    error::RegistryError,
+};
+use read::RegistryRead;
+use write::RegistryWrite;
+
+pub mod direct;
+pub mod hive;
+pub mod ops;
+mod raw;
+pub mod read;
+pub mod remote;
+pub mod write;
 
 pub mod transaction {
-    use std::cell::Cell;
+    use std::{
+        borrow::Cow,
+        cell::{Cell, RefCell},
+        ffi::OsStr,
+        os::windows::ffi::OsStrExt,
+        path::Path,
+        time::Duration,
+    };
 
     use windows::{
         Win32::{
             Foundation::{
-                E_ILLEGAL_STATE_CHANGE, ERROR_FILE_NOT_FOUND, ERROR_SUCCESS, HANDLE, WIN32_ERROR,
+                E_ILLEGAL_STATE_CHANGE, E_INVALIDARG, ERROR_ALREADY_EXISTS,
+                ERROR_ARITHMETIC_OVERFLOW, ERROR_FILE_NOT_FOUND, ERROR_INSUFFICIENT_BUFFER,
+                ERROR_SHARING_VIOLATION, ERROR_SUCCESS, ERROR_TRANSACTIONAL_CONFLICT, HANDLE,
+                WIN32_ERROR,
+            },
+            Security::{
+                DACL_SECURITY_INFORMATION, GROUP_SECURITY_INFORMATION, LABEL_SECURITY_INFORMATION,
+                OBJECT_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+                SACL_SECURITY_INFORMATION,
+            },
+            Storage::FileSystem::{
+                CommitTransaction, CreateTransaction, DELETE, RollbackTransaction, WRITE_DAC,
+                WRITE_OWNER,
             },
-            Storage::FileSystem::{CommitTransaction, CreateTransaction, RollbackTransaction},
             System::{
                 Registry::{
-                    HKEY, KEY_READ, KEY_WRITE, REG_BINARY, REG_DWORD, REG_EXPAND_SZ,
-                    REG_OPEN_CREATE_OPTIONS, REG_OPTION_NON_VOLATILE, REG_OPTION_VOLATILE,
-                    REG_QWORD, REG_SZ, REG_VALUE_TYPE, RegCreateKeyTransactedW, RegDeleteTreeW,
-                    RegDeleteValueW, RegOpenKeyTransactedW,
+                    HKEY, HKEY_CLASSES_ROOT, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE,
+                    KEY_ENUMERATE_SUB_KEYS, KEY_QUERY_VALUE, KEY_READ, KEY_SET_VALUE, KEY_WRITE,
+                    REG_BINARY, REG_CREATE_KEY_DISPOSITION, REG_CREATED_NEW_KEY, REG_DWORD,
+                    REG_EXPAND_SZ, REG_MULTI_SZ, REG_NONE, REG_NOTIFY_FILTER,
+                    REG_NOTIFY_THREAD_AGNOSTIC, REG_OPEN_CREATE_OPTIONS, REG_OPTION_NON_VOLATILE,
+                    REG_OPTION_VOLATILE, REG_QWORD, REG_SAM_FLAGS, REG_SZ, REG_VALUE_TYPE,
+                    RegCreateKeyTransactedW, RegDeleteTreeW, RegDeleteValueW, RegGetKeySecurity,
+                    RegNotifyChangeKeyValue, RegOpenKeyExW, RegOpenKeyTransactedW,
+                    RegSetKeySecurity,
                 },
-                Threading::INFINITE,
+                Threading::{CreateEventW, INFINITE},
             },
         },
-        core::{GUID, Owned, PCWSTR},
+        core::{GUID, Owned, PCWSTR, w},
     };
 
-    use crate::com::GuidExt;
+    use super::{
+        RegistrationScope, RegistryView,
+        ops::{RegOp, apply_ops, inverse_ops, snapshot},
+        raw::{expand_environment_strings, join_path, pcwstr_with_nul, to_wide},
+        read::RegistryRead,
+        write::RegistryWrite,
+    };
+    use crate::{com::GuidExt, error::RegistryError};
 
     pub struct Transaction {
         handle: Owned<HANDLE>,
         key_options: REG_OPEN_CREATE_OPTIONS,
-        committed: Cell<bool>,
+        view: RegistryView,
+        state: Cell<TransactionState>,
+        ops: RefCell<Vec<(HKEY, RegOp)>>,
+        undo: RefCell<Vec<Vec<(HKEY, RegOp)>>>,
+        checkpoints: Cell<usize>,
+        delete_retry: Option<DeleteRetry>,
+        record_ops: bool,
+        dry_run: bool,
+    }
+
+    /// How often a tree delete is retried while another handle keeps the subtree busy, see
+    /// [`Transaction::with_delete_retry`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct DeleteRetry {
+        /// The number of attempts, including the first one.
+        pub attempts: u32,
+        /// The wait before the first retry, doubled after each further one.
+        pub backoff: Duration,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum TransactionState {
+        Active,
+        Committed,
+        RolledBack,
+    }
+
+    /// Whether [`Key::create_subkey_with_disposition`] created a new key or opened an existing
+    /// one.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Disposition {
+        Created,
+        Opened,
+    }
+
+    /// A point within a [`Transaction`] that [`Transaction::rollback_to`] can return to.
+    #[must_use]
+    pub struct Checkpoint {
+        ops: usize,
+        undo: usize,
+    }
+
+    /// The operations applied through a [`Transaction`], relative to the root keys they were
+    /// applied to, so they can be re-run against a fresh transaction after a failed commit.
+    pub struct RecoverablePlan {
+        ops: Vec<(HKEY, RegOp)>,
+    }
+
+    impl RecoverablePlan {
+        pub fn ops(&self) -> &[(HKEY, RegOp)] {
+            &self.ops
+        }
+
+        pub fn replay(&self, transaction: &Transaction) -> windows::core::Result<()> {
+            self.ops
+                .iter()
+                .try_for_each(|(root, op)| op.apply(&Key::root_of(transaction, *root)?))
+        }
+    }
+
+    /// Converts a value size to the `u32` that `RegSetValueExW` takes.
+    fn checked_value_len(len: usize) -> windows::core::Result<u32> {
+        u32::try_from(len).map_err(|_| {
+            windows::core::Error::new(
+                ERROR_ARITHMETIC_OVERFLOW.to_hresult(),
+                format!(
+                    "registry values are limited to {} bytes, got {len}",
+                    u32::MAX
+                ),
+            )
+        })
+    }
+
+    fn pcwstr_to_string(value: PCWSTR) -> windows::core::Result<String> {
+        if value.is_null() {
+            Ok(String::new())
+        } else {
+            Ok(unsafe { value.to_string() }?)
+        }
+    }
+
+    /// Copies a value name for a [`RegOp`], with null naming the default value like the empty
+    /// name does.
+    fn wide_name(name: PCWSTR) -> Vec<u16> {
+        if name.is_null() {
+            Vec::new()
+        } else {
+            unsafe { name.as_wide() }.to_vec()
+        }
     }
 
     impl Transaction {
+        /// Creates a transaction for registry writes. Without KTM support this fails with an
+        /// error that converts into [`RegistryError::TransactionsUnsupported`].
         pub fn new(description: PCWSTR, volatile: bool) -> windows::core::Result<Self> {
-            Ok(Self {
-                handle: unsafe {
-                    Owned::new(CreateTransaction(
-                        std::ptr::null_mut(),
-                        std::ptr::null_mut(),
-                        0,
-                        0,
-                        0,
-                        INFINITE,
-                        description,
-                    )?)
-                },
+            Ok(Self::create(description, volatile, INFINITE)?)
+        }
+
+        /// Like [`Transaction::new`], but aborts the transaction if it is not committed within
+        /// `timeout`. Timeouts beyond the range of `CreateTransaction` mean no timeout.
+        pub fn with_timeout(
+            description: PCWSTR,
+            volatile: bool,
+            timeout: Duration,
+        ) -> Result<Self, RegistryError> {
+            if timeout.is_zero() {
+                return Err(windows::core::Error::from(E_INVALIDARG).into());
+            }
+
+            // Round up so that sub-millisecond timeouts do not turn into zero.
+            let milliseconds = timeout.as_nanos().div_ceil(1_000_000);
+
+            Self::create(
+                description,
+                volatile,
+                u32::try_from(milliseconds).unwrap_or(INFINITE),
+            )
+        }
+
+        fn create(
+            description: PCWSTR,
+            volatile: bool,
+            timeout: u32,
+        ) -> Result<Self, RegistryError> {
+            let handle = unsafe {
+                CreateTransaction(
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    0,
+                    0,
+                    0,
+                    timeout,
+                    description,
+                )?
+            };
+
+            Ok(unsafe { Self::from_handle(handle, volatile) })
+        }
+
+        /// Joins a transaction created elsewhere, such as by an installer, instead of creating
+        /// one. The returned `Transaction` owns `handle`: it closes it when dropped, and rolls the
+        /// transaction back unless it was committed through it first. Callers that need to keep
+        /// their own handle should pass a duplicate from `DuplicateHandle`.
+        ///
+        /// # Safety
+        ///
+        /// `handle` must be a valid KTM transaction handle that is not closed elsewhere.
+        pub unsafe fn from_handle(handle: HANDLE, volatile: bool) -> Self {
+            Self::with_handle(unsafe { Owned::new(handle) }, volatile, false)
+        }
+
+        fn with_handle(handle: Owned<HANDLE>, volatile: bool, dry_run: bool) -> Self {
+            Self {
+                handle,
                 key_options: if volatile {
                     REG_OPTION_VOLATILE
                 } else {
                     REG_OPTION_NON_VOLATILE
                 },
+                view: RegistryView::Default,
+                state: Cell::new(TransactionState::Active),
+                ops: RefCell::default(),
+                undo: RefCell::default(),
+                checkpoints: Cell::new(0),
+                delete_retry: None,
+                record_ops: dry_run,
+                dry_run,
+            }
+        }
 
-                committed: Cell::new(false),
-            })
+        /// Creates a transaction that never touches the registry: keys and values are only
+        /// recorded, so [`Transaction::recorded_ops`] shows what a registration would write.
+        /// Reads through its keys fail, as there is no underlying key to read from.
+        #[cfg(any(test, feature = "dry-run"))]
+        pub fn dry_run(volatile: bool) -> Self {
+            Self::with_handle(Owned::default(), volatile, true)
         }
 
+        /// Commits the transaction. [`Key`]s borrowed from it may still be alive: their handles
+        /// stay valid and are closed normally when dropped, but operations through them fail with
+        /// `ERROR_TRANSACTION_NOT_ACTIVE` from here on.
         pub fn commit(&self) -> windows::core::Result<()> {
-            if self.committed.get() {
+            if self.state.get() != TransactionState::Active {
                 return Err(E_ILLEGAL_STATE_CHANGE.into());
             }
 
-            unsafe {
-                CommitTransaction(*self.handle)?;
+            if !self.dry_run {
+                unsafe {
+                    CommitTransaction(*self.handle)?;
+                }
+            }
+
+            self.state.set(TransactionState::Committed);
+            Ok(())
+        }
+
+        /// Rolls the transaction back now rather than when it is dropped, reporting any failure.
+        pub fn rollback(&self) -> windows::core::Result<()> {
+            if self.state.get() != TransactionState::Active {
+                return Err(E_ILLEGAL_STATE_CHANGE.into());
+            }
+
+            if !self.dry_run {
+                unsafe {
+                    RollbackTransaction(*self.handle)?;
+                }
+            }
+
+            self.state.set(TransactionState::RolledBack);
+            Ok(())
+        }
+
+        pub fn is_committed(&self) -> bool {
+            self.state.get() == TransactionState::Committed
+        }
+
+        /// ORs `extra` into the options used for every key created through this transaction.
+        ///
+        /// `REG_OPTION_VOLATILE` and `REG_OPTION_NON_VOLATILE` are chosen by [`Transaction::new`]
+        /// and are mutually exclusive, as are `REG_OPTION_CREATE_LINK` and
+        /// `REG_OPTION_OPEN_LINK`. `REG_OPTION_BACKUP_RESTORE` ignores the requested access and
+        /// needs the backup or restore privilege.
+        pub fn with_options(mut self, extra: REG_OPEN_CREATE_OPTIONS) -> Self {
+            self.key_options |= extra;
+            self
+        }
+
+        /// Retries deleting a subtree that fails with `ERROR_SHARING_VIOLATION` or
+        /// `ERROR_TRANSACTIONAL_CONFLICT`, which happens during uninstall while Explorer still
+        /// holds handles below it. Without this, such deletes fail immediately.
+        pub fn with_delete_retry(mut self, retry: DeleteRetry) -> Self {
+            self.delete_retry = Some(retry);
+            self
+        }
+
+        /// Records every operation applied through this transaction, for
+        /// [`Transaction::recover`] and [`Transaction::recorded_ops`]. Off by default, as each
+        /// recorded value keeps a copy of its data until the transaction is dropped. Dry runs
+        /// always record.
+        pub fn with_recording(mut self) -> Self {
+            self.record_ops = true;
+            self
+        }
+
+        /// Selects the WOW64 view that every key is created or opened in.
+        pub fn with_view(mut self, view: RegistryView) -> Self {
+            self.view = view;
+            self
+        }
+
+        pub fn is_volatile(&self) -> bool {
+            self.key_options.contains(REG_OPTION_VOLATILE)
+        }
+
+        /// Gives up on this transaction, rolling it back, and returns the operations that were
+        /// applied through it so they can be replayed with [`RecoverablePlan::replay`]. The plan
+        /// is empty unless the transaction was created [`with_recording`](Self::with_recording).
+        pub fn recover(self) -> RecoverablePlan {
+            RecoverablePlan {
+                ops: self.ops.take(),
+            }
+        }
+
+        /// The operations applied through this transaction so far, relative to their root keys.
+        /// Empty unless the transaction was created [`with_recording`](Self::with_recording).
+        pub fn recorded_ops(&self) -> Vec<(HKEY, RegOp)> {
+            self.ops.borrow().clone()
+        }
+
+        /// Returns whether operations have to be built at all, which copies value data.
+        fn tracks_ops(&self) -> bool {
+            #[cfg(feature = "log")]
+            if log::log_enabled!(log::Level::Trace) {
+                return true;
+            }
+
+            self.record_ops || self.undo_enabled()
+        }
+
+        fn record(&self, root: HKEY, op: RegOp) {
+            if self.record_ops {
+                self.ops.borrow_mut().push((root, op));
+            }
+        }
+
+        /// Marks a point that [`Transaction::rollback_to`] can undo back to without aborting the
+        /// whole transaction.
+        ///
+        /// KTM has no nested transactions or savepoints, so this is emulated by the library: while
+        /// any checkpoint is held, every operation captures the state it overwrites, and rolling
+        /// back applies those captured inverses within the same transaction. Checkpoints that are
+        /// no longer needed should be given back with [`Transaction::release_checkpoint`] to stop
+        /// the capturing.
+        pub fn checkpoint(&self) -> Checkpoint {
+            self.checkpoints.set(self.checkpoints.get() + 1);
+
+            Checkpoint {
+                ops: self.ops.borrow().len(),
+                undo: self.undo.borrow().len(),
+            }
+        }
+
+        /// Undoes every operation applied since `checkpoint` was taken.
+        pub fn rollback_to(&self, checkpoint: Checkpoint) -> windows::core::Result<()> {
+            if checkpoint.undo > self.undo.borrow().len()
+                || checkpoint.ops > self.ops.borrow().len()
+            {
+                return Err(E_ILLEGAL_STATE_CHANGE.into());
+            }
+
+            let undo = self.undo.borrow_mut().split_off(checkpoint.undo);
+
+            for (root, op) in undo.into_iter().rev().flatten() {
+                op.apply(&Key::root_of(self, root)?)?;
+            }
+
+            // Applying the inverses recorded operations of their own, which cancel out with the
+            // ones being undone.
+            self.ops.borrow_mut().truncate(checkpoint.ops);
+            self.undo.borrow_mut().truncate(checkpoint.undo);
+            self.release_checkpoint(checkpoint);
+            Ok(())
+        }
+
+        /// Keeps every operation applied since `checkpoint` was taken. Once no checkpoint is held
+        /// anymore, operations stop capturing undo information.
+        pub fn release_checkpoint(&self, checkpoint: Checkpoint) {
+            let _ = checkpoint;
+            let remaining = self.checkpoints.get().saturating_sub(1);
+            self.checkpoints.set(remaining);
+
+            if remaining == 0 {
+                self.undo.borrow_mut().clear();
+            }
+        }
+
+        fn undo_enabled(&self) -> bool {
+            self.checkpoints.get() > 0
+        }
+
+        fn capture_undo(&self, before: &Key, op: &RegOp) -> windows::core::Result<()> {
+            if self.dry_run || !self.undo_enabled() {
+                return Ok(());
             }
 
-            self.committed.replace(true);
+            let inverse = inverse_ops(std::slice::from_ref(op), before)?
+                .into_iter()
+                .map(|inverse| (before.root, inverse.rebased(&before.path)))
+                .collect();
+
+            self.undo.borrow_mut().push(inverse);
             Ok(())
         }
+
+        fn create_key(
+            &self,
+            key: HKEY,
+            sub_key: PCWSTR,
+            class: PCWSTR,
+        ) -> windows::core::Result<(Owned<HKEY>, Disposition)> {
+            // Nothing is created in a dry run, so report every key as new.
+            if self.dry_run {
+                return Ok((Owned::default(), Disposition::Created));
+            }
+
+            let (key, disposition) = unsafe {
+                reg_create_key_transacted(
+                    key,
+                    sub_key,
+                    class,
+                    self.key_options,
+                    KEY_READ | KEY_WRITE | self.view.access(),
+                    *self.handle,
+                )?
+            };
+
+            Ok((unsafe { Owned::new(key) }, disposition))
+        }
+
+        fn open_key(&self, key: HKEY, sub_key: PCWSTR) -> windows::core::Result<Owned<HKEY>> {
+            if self.dry_run {
+                return Ok(Owned::default());
+            }
+
+            Ok(unsafe {
+                Owned::new(open_key_transacted(
+                    key,
+                    sub_key,
+                    KEY_READ | KEY_WRITE | self.view.access(),
+                    *self.handle,
+                )?)
+            })
+        }
+
+        /// Opens an existing root key without creating it, returning `None` if it is missing.
+        pub fn open_existing(
+            &self,
+            key: HKEY,
+            sub_key: PCWSTR,
+        ) -> windows::core::Result<Option<Key<'_>>> {
+            match self.open_key(key, sub_key) {
+                Ok(result) => Ok(Some(Key {
+                    transaction: self,
+                    key: result,
+                    merged_classes_root: key == HKEY_CLASSES_ROOT,
+                    root: key,
+                    path: pcwstr_to_string(sub_key)?,
+                })),
+                Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_FILE_NOT_FOUND) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+
+        /// Moves `src_name` below `src_parent` to `dst_name` below `dst_parent` by copying the
+        /// subtree and deleting the source, so the transaction leaves exactly one copy behind.
+        /// Fails with `ERROR_ALREADY_EXISTS` if the destination exists, unless `overwrite` is set.
+        pub fn move_subkey(
+            &self,
+            src_parent: &Key,
+            src_name: PCWSTR,
+            dst_parent: &Key,
+            dst_name: PCWSTR,
+            overwrite: bool,
+        ) -> windows::core::Result<()> {
+            if !std::ptr::eq(src_parent.transaction, self)
+                || !src_parent.same_transaction(dst_parent)
+            {
+                return Err(windows::core::Error::new(
+                    E_INVALIDARG,
+                    "both keys have to belong to this transaction",
+                ));
+            }
+
+            let mut ops = Vec::new();
+            snapshot(&src_parent.open_subkey(src_name)?, "", &mut ops)?;
+
+            if dst_parent.try_open_subkey(dst_name)?.is_some() {
+                if !overwrite {
+                    return Err(ERROR_ALREADY_EXISTS.into());
+                }
+
+                dst_parent.delete_subkey(dst_name)?;
+            }
+
+            apply_ops(&dst_parent.create_subkey(dst_name)?, &ops)?;
+            src_parent.delete_subkey(src_name)
+        }
+
+        /// Opens `sub_key` with `KEY_READ` only, reading through this transaction instead of the
+        /// live registry that [`ReadKey`](super::read::ReadKey) sees.
+        pub fn read_snapshot(
+            &self,
+            key: HKEY,
+            sub_key: PCWSTR,
+        ) -> windows::core::Result<SnapshotKey<'_>> {
+            Ok(SnapshotKey {
+                transaction: self,
+                key: self.open_read_key(key, sub_key)?,
+            })
+        }
+
+        fn open_read_key(&self, key: HKEY, sub_key: PCWSTR) -> windows::core::Result<Owned<HKEY>> {
+            let mut result = HKEY::default();
+
+            // There is no transaction to read through in a dry run.
+            if self.dry_run {
+                unsafe {
+                    RegOpenKeyExW(
+                        key,
+                        sub_key,
+                        None,
+                        KEY_READ | self.view.access(),
+                        &raw mut result,
+                    )
+                    .ok()?;
+                    return Ok(Owned::new(result));
+                }
+            }
+
+            Ok(unsafe {
+                Owned::new(open_key_transacted(
+                    key,
+                    sub_key,
+                    KEY_READ | self.view.access(),
+                    *self.handle,
+                )?)
+            })
+        }
+    }
+
+    /// A read-only key opened by [`Transaction::read_snapshot`].
+    pub struct SnapshotKey<'a> {
+        transaction: &'a Transaction,
+        key: Owned<HKEY>,
+    }
+
+    impl SnapshotKey<'_> {
+        pub fn open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Self> {
+            Ok(Self {
+                transaction: self.transaction,
+                key: self.transaction.open_read_key(*self.key, sub_key)?,
+            })
+        }
+    }
+
+    impl RegistryRead for SnapshotKey<'_> {
+        fn raw_handle(&self) -> HKEY {
+            *self.key
+        }
+
+        fn try_open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Option<Self>> {
+            match self.open_subkey(sub_key) {
+                Ok(key) => Ok(Some(key)),
+                Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_FILE_NOT_FOUND) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
     }
 
     impl Drop for Transaction {
         fn drop(&mut self) {
-            if !self.committed.get() {
+            // A dry run has no handle to roll back.
+            if self.state.get() == TransactionState::Active && !self.dry_run {
                 unsafe {
                     let _ = RollbackTransaction(*self.handle);
                 }
@@ -86,35 +630,45 @@ pub mod transaction {
     unsafe fn reg_create_key_transacted(
         key: HKEY,
         sub_key: PCWSTR,
+        class: PCWSTR,
         options: REG_OPEN_CREATE_OPTIONS,
+        access: REG_SAM_FLAGS,
         transaction: HANDLE,
-    ) -> windows::core::Result<HKEY> {
+    ) -> windows::core::Result<(HKEY, Disposition)> {
         let mut result = HKEY::default();
+        let mut disposition = REG_CREATE_KEY_DISPOSITION::default();
 
         unsafe {
             RegCreateKeyTransactedW(
                 key,
                 sub_key,
                 None,
-                None,
+                class,
                 options,
-                KEY_READ | KEY_WRITE,
+                access,
                 None,
                 &raw mut result,
-                None,
+                Some(&raw mut disposition),
                 transaction,
                 None,
             )
             .ok()?;
         }
 
-        Ok(result)
+        let disposition = if disposition == REG_CREATED_NEW_KEY {
+            Disposition::Created
+        } else {
+            Disposition::Opened
+        };
+
+        Ok((result, disposition))
     }
 
     #[allow(unused)]
     unsafe fn open_key_transacted(
         key: HKEY,
         sub_key: PCWSTR,
+        access: REG_SAM_FLAGS,
         transaction: HANDLE,
     ) -> windows::core::Result<HKEY> {
         let mut result = HKEY::default();
@@ -124,7 +678,7 @@ pub mod transaction {
                 key,
                 sub_key,
                 None,
-                KEY_READ | KEY_WRITE,
+                access,
                 &raw mut result,
                 transaction,
                 None,
@@ -135,89 +689,461 @@ pub mod transaction {
         Ok(result)
     }
 
+    /// A key opened within a [`Transaction`]. The borrow guarantees the transaction handle
+    /// outlives every key handle, so keys are always closed before the transaction is rolled
+    /// back on drop.
+    ///
+    /// `open_*` methods never create keys, while `create_*` methods create missing ones.
     pub struct Key<'a> {
         transaction: &'a Transaction,
         key: Owned<HKEY>,
+        merged_classes_root: bool,
+        root: HKEY,
+        path: String,
+    }
+
+    impl RegistryRead for Key<'_> {
+        fn raw_handle(&self) -> HKEY {
+            *self.key
+        }
+
+        /// Probes with `KEY_READ` only, so keys that may be read but not written count as well.
+        fn subkey_exists(&self, sub_key: PCWSTR) -> windows::core::Result<bool> {
+            match self.transaction.open_read_key(*self.key, sub_key) {
+                Ok(_) => Ok(true),
+                Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_FILE_NOT_FOUND) => Ok(false),
+                Err(e) => Err(e),
+            }
+        }
+
+        fn try_open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Option<Self>> {
+            match self.open_subkey(sub_key) {
+                Ok(key) => Ok(Some(key)),
+                Err(RegistryError::NotFound(_)) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+
+    /// Forwards to the inherent methods, so writes through the trait are recorded and undone
+    /// like any other.
+    impl RegistryWrite for Key<'_> {
+        fn create_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Self> {
+            Key::create_subkey(self, sub_key)
+        }
+
+        fn delete_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<()> {
+            Key::delete_subkey(self, sub_key)
+        }
+
+        fn set_raw(
+            &self,
+            name: PCWSTR,
+            value_type: REG_VALUE_TYPE,
+            data: &[u8],
+        ) -> windows::core::Result<()> {
+            Key::set_raw(self, name, value_type, data)
+        }
+
+        fn delete_value(&self, name: PCWSTR) -> windows::core::Result<()> {
+            Key::delete_value(self, name)
+        }
     }
 
     impl<'a> Key<'a> {
+        /// Creates or opens `sub_key` below `key` within `transaction`. Converting the error into
+        /// a [`RegistryError`] tells cases such as missing KTM support apart.
         pub fn predefined(
             transaction: &'a Transaction,
             key: HKEY,
             sub_key: PCWSTR,
         ) -> windows::core::Result<Self> {
-            let mut result = HKEY::default();
+            Ok(Self::predefined_with_class(
+                transaction,
+                key,
+                sub_key,
+                PCWSTR::null(),
+            )?)
+        }
 
-            unsafe {
-                RegCreateKeyTransactedW(
-                    key,
-                    sub_key,
-                    None,
-                    None,
-                    transaction.key_options,
-                    KEY_READ | KEY_WRITE,
-                    None,
-                    &raw mut result,
-                    None,
-                    *transaction.handle,
-                    None,
-                )
-                .ok()?;
+        /// Like [`Key::predefined`], but tags a newly created key with the class string `class`.
+        pub fn predefined_with_class(
+            transaction: &'a Transaction,
+            key: HKEY,
+            sub_key: PCWSTR,
+            class: PCWSTR,
+        ) -> Result<Self, RegistryError> {
+            let path = pcwstr_to_string(sub_key)?;
+            let op = RegOp::CreateKey { path: path.clone() };
+
+            if transaction.undo_enabled() {
+                transaction.capture_undo(&Self::root_of(transaction, key)?, &op)?;
+            }
+
+            let (result, _) = transaction.create_key(key, sub_key, class)?;
+
+            if !path.is_empty() {
+                transaction.record(key, op);
+            }
+
+            #[cfg(feature = "log")]
+            if key == HKEY_CLASSES_ROOT {
+                log::warn!(
+                    "writing through the merged HKEY_CLASSES_ROOT view; \
+                     prefer HKEY_LOCAL_MACHINE\\Software\\Classes or HKEY_CURRENT_USER\\Software\\Classes"
+                );
             }
 
             Ok(Self {
                 transaction,
-                key: unsafe {
-                    Owned::new(reg_create_key_transacted(
-                        key,
-                        sub_key,
-                        transaction.key_options,
-                        *transaction.handle,
-                    )?)
-                },
+                key: result,
+                merged_classes_root: key == HKEY_CLASSES_ROOT,
+                root: key,
+                path,
             })
         }
 
-        pub fn create_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Key<'a>> {
+        /// Opens `Software\Classes` in `HKEY_LOCAL_MACHINE` or `HKEY_CURRENT_USER`. Per-user
+        /// registrations, including `LocalServer32` ones, use the same layout below it as
+        /// per-machine ones.
+        pub fn classes_root(
+            transaction: &'a Transaction,
+            scope: RegistrationScope,
+        ) -> Result<Self, RegistryError> {
+            let root = match scope {
+                RegistrationScope::PerMachine => HKEY_LOCAL_MACHINE,
+                RegistrationScope::PerUser => HKEY_CURRENT_USER,
+            };
+
+            Self::predefined_with_class(transaction, root, w!("Software\\Classes"), PCWSTR::null())
+        }
+
+        /// Opens `key` itself without recording an operation.
+        fn root_of(transaction: &'a Transaction, key: HKEY) -> windows::core::Result<Self> {
             Ok(Self {
-                transaction: self.transaction,
-                key: unsafe {
-                    Owned::new(reg_create_key_transacted(
-                        *self.key,
-                        sub_key,
-                        self.transaction.key_options,
-                        *self.transaction.handle,
-                    )?)
-                },
+                transaction,
+                key: transaction.create_key(key, w!(""), PCWSTR::null())?.0,
+                merged_classes_root: key == HKEY_CLASSES_ROOT,
+                root: key,
+                path: String::new(),
             })
         }
 
-        #[allow(unused)]
-        pub fn open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Key<'a>> {
-            Ok(Self {
-                transaction: self.transaction,
-                key: unsafe {
-                    Owned::new(open_key_transacted(
-                        *self.key,
-                        sub_key,
-                        *self.transaction.handle,
-                    )?)
+        /// Returns whether this key was opened through the merged `HKEY_CLASSES_ROOT` view, where
+        /// writes may land in either `HKEY_CURRENT_USER` or `HKEY_LOCAL_MACHINE`.
+        pub fn is_merged_classes_root(&self) -> bool {
+            self.merged_classes_root
+        }
+
+        /// Returns whether keys created through this key's transaction are volatile.
+        pub fn is_volatile(&self) -> bool {
+            self.transaction.is_volatile()
+        }
+
+        pub fn transaction(&self) -> &'a Transaction {
+            self.transaction
+        }
+
+        /// Returns whether both keys were opened within the same [`Transaction`].
+        pub fn same_transaction(&self, other: &Key) -> bool {
+            std::ptr::eq(self.transaction, other.transaction)
+        }
+
+        /// The key this key was opened from through [`Key::predefined`] or
+        /// [`Transaction::open_existing`].
+        pub fn root(&self) -> HKEY {
+            self.root
+        }
+
+        fn subkey_path(&self, sub_key: PCWSTR) -> windows::core::Result<String> {
+            Ok(join_path(&self.path, &pcwstr_to_string(sub_key)?))
+        }
+
+        /// Captures the undo information for `op`, which is relative to this key.
+        fn prepare(&self, op: &RegOp) -> windows::core::Result<()> {
+            self.transaction.capture_undo(self, op)
+        }
+
+        /// Records `op`, which is relative to this key, as applied.
+        fn record(&self, op: RegOp) {
+            let op = op.rebased(&self.path);
+
+            #[cfg(feature = "log")]
+            log::trace!("{op:?}");
+
+            self.transaction.record(self.root, op);
+        }
+
+        pub fn create_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Key<'a>> {
+            Ok(self.create_subkey_with_disposition(sub_key)?.0)
+        }
+
+        /// Like [`Key::create_subkey`], but also reports whether the key already existed, so
+        /// installers can tell a fresh registration from an upgrade.
+        pub fn create_subkey_with_disposition(
+            &self,
+            sub_key: PCWSTR,
+        ) -> windows::core::Result<(Key<'a>, Disposition)> {
+            self.create_subkey_internal(sub_key, PCWSTR::null())
+        }
+
+        /// Like [`Key::create_subkey`], but tags a newly created key with the class string
+        /// `class`. Existing keys keep their class.
+        pub fn create_subkey_with_class(
+            &self,
+            sub_key: PCWSTR,
+            class: PCWSTR,
+        ) -> windows::core::Result<Key<'a>> {
+            Ok(self.create_subkey_internal(sub_key, class)?.0)
+        }
+
+        fn create_subkey_internal(
+            &self,
+            sub_key: PCWSTR,
+            class: PCWSTR,
+        ) -> windows::core::Result<(Key<'a>, Disposition)> {
+            let path = self.subkey_path(sub_key)?;
+            let op = RegOp::CreateKey {
+                path: pcwstr_to_string(sub_key)?,
+            };
+
+            self.prepare(&op)?;
+            let (key, disposition) = self.transaction.create_key(*self.key, sub_key, class)?;
+            self.record(op);
+
+            Ok((
+                Self {
+                    transaction: self.transaction,
+                    key,
+                    merged_classes_root: self.merged_classes_root,
+                    root: self.root,
+                    path,
                 },
+                disposition,
+            ))
+        }
+
+        /// Opens an existing subkey for reading and writing, failing with
+        /// [`RegistryError::NotFound`] if it does not exist.
+        pub fn open_subkey(&self, sub_key: PCWSTR) -> Result<Key<'a>, RegistryError> {
+            let path = self.subkey_path(sub_key)?;
+
+            Ok(Self {
+                transaction: self.transaction,
+                key: self.transaction.open_key(*self.key, sub_key)?,
+                merged_classes_root: self.merged_classes_root,
+                root: self.root,
+                path,
+            })
+        }
+
+        /// Opens an existing subkey with `KEY_READ` only, failing with
+        /// [`RegistryError::NotFound`] if it does not exist.
+        pub fn open_subkey_readonly(
+            &self,
+            sub_key: PCWSTR,
+        ) -> Result<SnapshotKey<'a>, RegistryError> {
+            Ok(SnapshotKey {
+                transaction: self.transaction,
+                key: self.transaction.open_read_key(*self.key, sub_key)?,
             })
         }
 
+        /// Replaces the parts of this key's security descriptor selected by `info`.
+        ///
+        /// Key security is not transacted: the change applies immediately and stays in place even
+        /// if the transaction is rolled back.
+        pub fn set_security(
+            &self,
+            descriptor: PSECURITY_DESCRIPTOR,
+            info: OBJECT_SECURITY_INFORMATION,
+        ) -> windows::core::Result<()> {
+            if self.transaction.dry_run {
+                return Ok(());
+            }
+
+            // Defined in Win32::System::SystemServices, which is not worth enabling for one constant.
+            const ACCESS_SYSTEM_SECURITY: u32 = 0x0100_0000;
+
+            let mut access = REG_SAM_FLAGS(0);
+
+            if info.contains(DACL_SECURITY_INFORMATION) {
+                access |= REG_SAM_FLAGS(WRITE_DAC.0);
+            }
+
+            if info.contains(OWNER_SECURITY_INFORMATION)
+                || info.contains(GROUP_SECURITY_INFORMATION)
+                || info.contains(LABEL_SECURITY_INFORMATION)
+            {
+                access |= REG_SAM_FLAGS(WRITE_OWNER.0);
+            }
+
+            if info.contains(SACL_SECURITY_INFORMATION) {
+                access |= REG_SAM_FLAGS(ACCESS_SYSTEM_SECURITY);
+            }
+
+            unsafe {
+                let key = Owned::new(open_key_transacted(
+                    *self.key,
+                    w!(""),
+                    access,
+                    *self.transaction.handle,
+                )?);
+
+                RegSetKeySecurity(*key, info, descriptor).ok()
+            }
+        }
+
+        /// Returns the parts of this key's security descriptor selected by `info`, in
+        /// self-relative form.
+        pub fn get_security(
+            &self,
+            info: OBJECT_SECURITY_INFORMATION,
+        ) -> windows::core::Result<Vec<u8>> {
+            let mut descriptor = Vec::<u8>::new();
+
+            loop {
+                let mut size = descriptor.len() as u32;
+
+                match unsafe {
+                    RegGetKeySecurity(
+                        *self.key,
+                        info,
+                        Some(PSECURITY_DESCRIPTOR(descriptor.as_mut_ptr().cast())),
+                        &raw mut size,
+                    )
+                } {
+                    ERROR_SUCCESS => {
+                        descriptor.truncate(size as usize);
+                        return Ok(descriptor);
+                    }
+                    ERROR_INSUFFICIENT_BUFFER => descriptor.resize(size as usize, 0),
+                    e => return Err(e.into()),
+                }
+            }
+        }
+
+        /// Arms a change notification on this key against a new manual-reset event, which is
+        /// signaled on the first matching change. The notification has to be re-armed after it
+        /// fires.
+        ///
+        /// The notification is not tied to the calling thread, so it survives that thread
+        /// exiting, but it is tied to this key: closing the key signals the event and ends the
+        /// notification, so the key has to outlive any wait on the event.
+        pub fn change_notification(
+            &self,
+            filter: REG_NOTIFY_FILTER,
+            watch_subtree: bool,
+        ) -> windows::core::Result<Owned<HANDLE>> {
+            let event = unsafe { Owned::new(CreateEventW(None, true, false, PCWSTR::null())?) };
+
+            unsafe {
+                RegNotifyChangeKeyValue(
+                    *self.key,
+                    watch_subtree,
+                    filter | REG_NOTIFY_THREAD_AGNOSTIC,
+                    Some(*event),
+                    true,
+                )
+                .ok()?;
+            }
+
+            Ok(event)
+        }
+
         pub fn delete_subkey(&self, subkey: PCWSTR) -> windows::core::Result<()> {
-            self.delete_tree_internal(subkey)
+            // A null subkey empties this key rather than deleting it.
+            let op = if subkey.is_null() {
+                RegOp::ClearKey {
+                    path: String::new(),
+                }
+            } else {
+                RegOp::DeleteKey {
+                    path: pcwstr_to_string(subkey)?,
+                }
+            };
+
+            self.prepare(&op)?;
+            self.delete_tree_internal(subkey)?;
+            self.record(op);
+            Ok(())
+        }
+
+        /// Renames the subkey `old` to `new` by copying it and deleting the original.
+        /// `RegRenameKey` would be faster, but it ignores the transaction, so a rollback would
+        /// not undo it. Fails with `ERROR_ALREADY_EXISTS` if `new` exists.
+        pub fn rename_subkey(&self, old: PCWSTR, new: PCWSTR) -> windows::core::Result<()> {
+            self.transaction.move_subkey(self, old, self, new, false)
+        }
+
+        /// Copies the values and subkeys of this key into `dest`, keeping value types. The whole
+        /// subtree is read before anything is written, so `dest` may lie below this key without
+        /// the copy recursing into itself.
+        pub fn copy_tree(&self, dest: &Key) -> windows::core::Result<()> {
+            let mut ops = Vec::new();
+            snapshot(self, "", &mut ops)?;
+            apply_ops(dest, &ops)
         }
 
+        /// Deletes the values and subkeys of this key, keeping the key itself.
         pub fn delete_tree(&self) -> windows::core::Result<()> {
-            self.delete_tree_internal(PCWSTR::null())
+            self.delete_subkey(PCWSTR::null())
         }
 
         fn delete_tree_internal(&self, subkey: PCWSTR) -> windows::core::Result<()> {
-            match unsafe { RegDeleteTreeW(*self.key, subkey) } {
-                ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => Ok(()),
-                e => e.ok(),
+            if self.transaction.dry_run {
+                return Ok(());
+            }
+
+            // Emptying a key needs DELETE on the key itself, which KEY_READ | KEY_WRITE does not
+            // include; a named subkey is opened by RegDeleteTreeW with the rights it needs.
+            let reopened = if subkey.is_null() {
+                Some(unsafe {
+                    Owned::new(open_key_transacted(
+                        *self.key,
+                        w!(""),
+                        REG_SAM_FLAGS(DELETE.0)
+                            | KEY_ENUMERATE_SUB_KEYS
+                            | KEY_QUERY_VALUE
+                            | KEY_SET_VALUE,
+                        *self.transaction.handle,
+                    )?)
+                })
+            } else {
+                None
+            };
+
+            let key = reopened.as_deref().copied().unwrap_or(*self.key);
+            let DeleteRetry {
+                attempts,
+                mut backoff,
+            } = self.transaction.delete_retry.unwrap_or(DeleteRetry {
+                attempts: 1,
+                backoff: Duration::ZERO,
+            });
+
+            let mut attempt = 1;
+
+            loop {
+                match unsafe { RegDeleteTreeW(key, subkey) } {
+                    ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => return Ok(()),
+                    ERROR_SHARING_VIOLATION | ERROR_TRANSACTIONAL_CONFLICT
+                        if attempt < attempts =>
+                    {
+                        std::thread::sleep(backoff);
+                        backoff = backoff.saturating_mul(2);
+                        attempt += 1;
+                    }
+                    e @ (ERROR_SHARING_VIOLATION | ERROR_TRANSACTIONAL_CONFLICT)
+                        if attempts > 1 =>
+                    {
+                        return Err(windows::core::Error::new(
+                            e.to_hresult(),
+                            format!("subtree still in use after {attempts} delete attempts"),
+                        ));
+                    }
+                    e => return e.ok(),
+                }
             }
         }
 
@@ -225,53 +1151,145 @@ pub mod transaction {
             self.set_value(name, Some(&value.to_le_bytes()), REG_DWORD)
         }
 
+        /// Writes a bitmask as a `REG_DWORD`, to be read back with
+        /// [`RegistryRead::get_flags`].
+        ///
+        /// ```ignore
+        /// struct Sfgao(u32);
+        ///
+        /// impl From<Sfgao> for u32 {
+        ///     fn from(value: Sfgao) -> Self {
+        ///         value.0
+        ///     }
+        /// }
+        ///
+        /// // SFGAO_FOLDER | SFGAO_HASSUBFOLDER
+        /// shell_folder.set_flags(w!("Attributes"), Sfgao(0x2000_0000 | 0x8000_0000))?;
+        /// ```
+        pub fn set_flags<F: Into<u32>>(&self, name: PCWSTR, flags: F) -> windows::core::Result<()> {
+            self.set_u32(name, flags.into())
+        }
+
         #[allow(unused)]
         pub fn set_u64(&self, name: PCWSTR, value: u64) -> windows::core::Result<()> {
             self.set_value(name, Some(&value.to_le_bytes()), REG_QWORD)
         }
 
+        /// Writes the two's-complement bit pattern of `value` as a `REG_DWORD`.
+        pub fn set_i32(&self, name: PCWSTR, value: i32) -> windows::core::Result<()> {
+            self.set_value(name, Some(&value.to_le_bytes()), REG_DWORD)
+        }
+
+        /// Writes the two's-complement bit pattern of `value` as a `REG_QWORD`.
+        pub fn set_i64(&self, name: PCWSTR, value: i64) -> windows::core::Result<()> {
+            self.set_value(name, Some(&value.to_le_bytes()), REG_QWORD)
+        }
+
+        /// Writes a `REG_NONE` value without data, for values that only mark something by
+        /// existing.
+        pub fn set_none(&self, name: PCWSTR) -> windows::core::Result<()> {
+            self.set_value(name, Some(&[0u8; 0]), REG_NONE)
+        }
+
         pub fn set_binary(&self, name: PCWSTR, value: &[u8]) -> windows::core::Result<()> {
             self.set_value(name, Some(value), REG_BINARY)
         }
 
+        /// Writes `len` bytes read from `reader` as a `REG_BINARY` value, so data coming from a
+        /// file does not need to be buffered by the caller first.
+        pub fn set_binary_from_reader(
+            &self,
+            name: PCWSTR,
+            mut reader: impl std::io::Read,
+            len: usize,
+        ) -> windows::core::Result<()> {
+            checked_value_len(len)?;
+
+            let mut data = vec![0u8; len];
+            reader.read_exact(&mut data)?;
+
+            // Handing over the buffer lets a recorded operation keep it instead of a copy.
+            self.set_value_bytes(name, Some(Cow::Owned(data)), REG_BINARY)
+        }
+
         #[allow(unused)]
         pub fn set_str(&self, name: PCWSTR, value: &str) -> windows::core::Result<()> {
-            self.set_value(
-                name,
-                Some(&value.encode_utf16().collect::<Vec<_>>()),
-                REG_SZ,
-            )
+            self.set_value(name, Some(&to_wide(value)), REG_SZ)
         }
 
         #[allow(unused)]
         pub fn set_str_expand(&self, name: PCWSTR, value: &str) -> windows::core::Result<()> {
-            self.set_value(
-                name,
-                Some(&value.encode_utf16().collect::<Vec<_>>()),
-                REG_EXPAND_SZ,
-            )
+            self.set_value(name, Some(&to_wide(value)), REG_EXPAND_SZ)
         }
 
-        pub fn set_pcwstr(&self, name: PCWSTR, value: PCWSTR) -> windows::core::Result<()> {
+        /// Writes `values` as a single `;`-separated `REG_EXPAND_SZ`, the convention used by
+        /// `PATH`-like values where each entry may reference `%VARIABLES%`. Empty entries are
+        /// rejected, as [`Key::get_multi_str_expand`] skips them like `PATH` lookups do.
+        pub fn set_multi_str_expand(
+            &self,
+            name: PCWSTR,
+            values: &[&str],
+        ) -> windows::core::Result<()> {
+            if let Some(value) = values.iter().find(|value| value.contains(';')) {
+                return Err(windows::core::Error::new(
+                    E_INVALIDARG,
+                    format!("entry {value:?} contains the ';' separator"),
+                ));
+            }
+
+            if values.iter().any(|value| value.is_empty()) {
+                return Err(windows::core::Error::new(
+                    E_INVALIDARG,
+                    "empty entries cannot be read back",
+                ));
+            }
+
+            self.set_value(name, Some(&to_wide(&values.join(";"))), REG_EXPAND_SZ)
+        }
+
+        /// Writes `value` as a nul-terminated `REG_SZ` without going through `str`, preserving
+        /// unpaired surrogates.
+        pub fn set_os_str(&self, name: PCWSTR, value: &OsStr) -> windows::core::Result<()> {
             self.set_value(
                 name,
-                if value.is_null() {
-                    None
-                } else {
-                    Some(unsafe { value.as_wide() })
-                },
+                Some(
+                    &value
+                        .encode_wide()
+                        .chain(std::iter::once(0))
+                        .collect::<Vec<_>>(),
+                ),
                 REG_SZ,
             )
         }
 
+        pub fn set_path(&self, name: PCWSTR, value: &Path) -> windows::core::Result<()> {
+            self.set_os_str(name, value.as_os_str())
+        }
+
+        /// Writes a `REG_MULTI_SZ`, terminating each string and the list itself with a nul.
+        pub fn set_multi_sz(&self, name: PCWSTR, values: &[&str]) -> windows::core::Result<()> {
+            let mut data = values
+                .iter()
+                .flat_map(|value| value.encode_utf16().chain(std::iter::once(0)))
+                .collect::<Vec<_>>();
+
+            // An empty list still needs both terminators to be a valid multi-string.
+            if values.is_empty() {
+                data.push(0);
+            }
+
+            data.push(0);
+            self.set_value(name, Some(&data), REG_MULTI_SZ)
+        }
+
+        pub fn set_pcwstr(&self, name: PCWSTR, value: PCWSTR) -> windows::core::Result<()> {
+            self.set_value(name, Some(unsafe { pcwstr_with_nul(&value) }), REG_SZ)
+        }
+
         pub fn set_pcwstr_expand(&self, name: PCWSTR, value: PCWSTR) -> windows::core::Result<()> {
             self.set_value(
                 name,
-                if value.is_null() {
-                    None
-                } else {
-                    Some(unsafe { value.as_wide() })
-                },
+                Some(unsafe { pcwstr_with_nul(&value) }),
                 REG_EXPAND_SZ,
             )
         }
@@ -280,12 +1298,70 @@ pub mod transaction {
             self.set_value(name, Some(&value.to_wide()), REG_SZ)
         }
 
+        /// Sets the key's unnamed default value.
+        pub fn set_default_str(&self, value: &str) -> windows::core::Result<()> {
+            self.set_str(PCWSTR::null(), value)
+        }
+
+        pub fn set_default_pcwstr(&self, value: PCWSTR) -> windows::core::Result<()> {
+            self.set_pcwstr(PCWSTR::null(), value)
+        }
+
+        pub fn set_default_guid(&self, value: &GUID) -> windows::core::Result<()> {
+            self.set_guid(PCWSTR::null(), value)
+        }
+
+        pub fn set_raw(
+            &self,
+            name: PCWSTR,
+            value_type: REG_VALUE_TYPE,
+            data: &[u8],
+        ) -> windows::core::Result<()> {
+            self.set_value(name, Some(data), value_type)
+        }
+
         fn set_value<T>(
             &self,
             name: PCWSTR,
             value: Option<&[T]>,
             value_type: REG_VALUE_TYPE,
         ) -> windows::core::Result<()> {
+            let data = value.map(|v| unsafe {
+                Cow::Borrowed(std::slice::from_raw_parts(
+                    v.as_ptr().cast::<u8>(),
+                    std::mem::size_of_val(v),
+                ))
+            });
+
+            self.set_value_bytes(name, data, value_type)
+        }
+
+        /// Writes `data`, with `None` passing a null buffer. An operation is only built when the
+        /// transaction tracks them, and then takes over owned data instead of copying it.
+        fn set_value_bytes(
+            &self,
+            name: PCWSTR,
+            data: Option<Cow<'_, [u8]>>,
+            value_type: REG_VALUE_TYPE,
+        ) -> windows::core::Result<()> {
+            let len = checked_value_len(data.as_deref().map_or(0, <[u8]>::len))?;
+
+            // Undo information only depends on the value's name, so the data is filled in once
+            // the write has gone through.
+            let op = if self.transaction.tracks_ops() {
+                let op = RegOp::SetValue {
+                    path: String::new(),
+                    name: wide_name(name),
+                    value_type,
+                    data: Vec::new(),
+                };
+
+                self.prepare(&op)?;
+                Some(op)
+            } else {
+                None
+            };
+
             unsafe extern "system" {
                 #[allow(unused)]
                 fn RegSetValueExW(
@@ -298,24 +1374,157 @@ pub mod transaction {
                 ) -> WIN32_ERROR;
             }
 
-            unsafe {
-                RegSetValueExW(
-                    *self.key,
-                    name,
-                    0,
-                    value_type,
-                    value.map_or(std::ptr::null(), |v| v.as_ptr().cast()),
-                    (value.map_or(0, |v| v.len()) * std::mem::size_of::<T>()) as u32,
-                )
-                .ok()
+            if !self.transaction.dry_run {
+                unsafe {
+                    RegSetValueExW(
+                        *self.key,
+                        name,
+                        0,
+                        value_type,
+                        data.as_deref().map_or(std::ptr::null(), <[u8]>::as_ptr),
+                        len,
+                    )
+                    .ok()?;
+                }
             }
+
+            if let Some(mut op) = op {
+                if let (RegOp::SetValue { data: recorded, .. }, Some(data)) = (&mut op, data) {
+                    *recorded = data.into_owned();
+                }
+
+                self.record(op);
+            }
+
+            Ok(())
+        }
+
+        /// Adds `delta` to a `REG_DWORD` value, treating a missing value as zero, and returns the
+        /// new value. The read and the write both happen inside the transaction, so concurrent
+        /// installers are serialized at commit.
+        pub fn increment_u32(&self, name: PCWSTR, delta: i64) -> windows::core::Result<u32> {
+            let current = self.get_u32(name)?.unwrap_or(0);
+            let value = i64::from(current)
+                .checked_add(delta)
+                .and_then(|value| u32::try_from(value).ok())
+                .ok_or_else(|| windows::core::Error::from(ERROR_ARITHMETIC_OVERFLOW))?;
+
+            self.set_u32(name, value)?;
+            Ok(value)
+        }
+
+        /// Reads a `;`-separated list written by [`Key::set_multi_str_expand`], optionally
+        /// expanding environment variables in each entry.
+        pub fn get_multi_str_expand(
+            &self,
+            name: PCWSTR,
+            expand: bool,
+        ) -> windows::core::Result<Option<Vec<String>>> {
+            let Some(value) = self.get_str(name)? else {
+                return Ok(None);
+            };
+
+            value
+                .split(';')
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| {
+                    if expand {
+                        expand_environment_strings(entry)
+                    } else {
+                        Ok(entry.to_owned())
+                    }
+                })
+                .collect::<windows::core::Result<_>>()
+                .map(Some)
         }
 
         pub fn delete_value(&self, name: PCWSTR) -> windows::core::Result<()> {
-            match unsafe { RegDeleteValueW(*self.key, name) } {
-                ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => Ok(()),
-                e => e.ok(),
+            let op = RegOp::DeleteValue {
+                path: String::new(),
+                name: wide_name(name),
+            };
+
+            self.prepare(&op)?;
+
+            if !self.transaction.dry_run {
+                match unsafe { RegDeleteValueW(*self.key, name) } {
+                    ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => {}
+                    e => e.ok()?,
+                }
+            }
+
+            self.record(op);
+            Ok(())
+        }
+
+        pub fn delete_value_at(&self, sub_key: PCWSTR, name: PCWSTR) -> windows::core::Result<()> {
+            let op = RegOp::DeleteValue {
+                path: pcwstr_to_string(sub_key)?,
+                name: wide_name(name),
+            };
+
+            self.prepare(&op)?;
+
+            let key = match self.transaction.open_key(*self.key, sub_key) {
+                Ok(key) => key,
+                Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_FILE_NOT_FOUND) => {
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+
+            if !self.transaction.dry_run {
+                match unsafe { RegDeleteValueW(*key, name) } {
+                    ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => {}
+                    e => e.ok()?,
+                }
             }
+
+            self.record(op);
+            Ok(())
+        }
+    }
+}
+
+/// The WOW64 view of the registry that keys are opened in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RegistryView {
+    /// The view matching the bitness of the current process.
+    #[default]
+    Default,
+    Force32,
+    Force64,
+}
+
+impl RegistryView {
+    fn access(self) -> REG_SAM_FLAGS {
+        match self {
+            Self::Default => REG_SAM_FLAGS(0),
+            Self::Force32 => KEY_WOW64_32KEY,
+            Self::Force64 => KEY_WOW64_64KEY,
+        }
+    }
+}
+
+/// Whether classes are registered for all users or only the current one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationScope {
+    PerMachine,
+    PerUser,
+}
+
+/// The predefined keys that can be reached on another machine or have a hive mounted under them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PredefinedKey {
+    LocalMachine,
+    Users,
+}
+
+impl From<PredefinedKey> for HKEY {
+    fn from(value: PredefinedKey) -> Self {
+        match value {
+            PredefinedKey::LocalMachine => HKEY_LOCAL_MACHINE,
+            PredefinedKey::Users => HKEY_USERS,
         }
     }
 }
@@ -324,13 +1533,22 @@ pub mod transaction {
 pub struct NullTerminatedSlice<'a>(&'a [u16]);
 
 impl<'a> NullTerminatedSlice<'a> {
+    /// Returns `None` unless `slice` ends in its only nul.
     pub fn new(slice: &'a [u16]) -> Option<Self> {
-        if slice.last() != Some(&0u16) {
-            None
-        } else {
-            Some(Self(slice))
+        match slice.split_last() {
+            Some((0, rest)) if !rest.contains(&0) => Some(Self(slice)),
+            _ => None,
         }
     }
+
+    /// Wraps `slice` without checking it.
+    ///
+    /// # Safety
+    ///
+    /// `slice` must end in a nul and contain no other nuls.
+    pub unsafe fn new_unchecked(slice: &'a [u16]) -> Self {
+        Self(slice)
+    }
 }
 
 impl Deref for NullTerminatedSlice<'_> {
@@ -341,57 +1559,1723 @@ impl Deref for NullTerminatedSlice<'_> {
     }
 }
 
-pub fn register_com_extension<'a, T: CoClass>(
-    classes: &'a Key,
-    module_path: NullTerminatedSlice,
-    description: PCWSTR,
-    apartment_type: PCWSTR,
-) -> windows::core::Result<Key<'a>> {
-    let clsid_string = T::CLSID.to_wide();
-    let com_object = classes
-        .create_subkey(w!("CLSID"))?
-        .create_subkey(PCWSTR::from_raw(clsid_string.as_ptr()))?;
+/// An owned, nul-terminated UTF-16 string that can be borrowed as a [`NullTerminatedSlice`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NullTerminatedWideString(Vec<u16>);
 
-    com_object.set_pcwstr(PCWSTR::null(), description)?;
+impl NullTerminatedWideString {
+    /// Encodes `value`, returning `None` if it contains a nul.
+    pub fn new(value: &str) -> Option<Self> {
+        if value.contains('\0') {
+            None
+        } else {
+            Some(Self(
+                value.encode_utf16().chain(std::iter::once(0)).collect(),
+            ))
+        }
+    }
 
-    com_object
-        .create_subkey(w!("ProgId"))?
-        .set_pcwstr(PCWSTR::null(), T::PROG_ID)?;
+    pub fn as_slice(&self) -> NullTerminatedSlice<'_> {
+        NullTerminatedSlice(&self.0)
+    }
+}
 
-    com_object
-        .create_subkey(w!("VersionIndependentProgId"))?
-        .set_pcwstr(PCWSTR::null(), T::VERSION_INDEPENDENT_PROG_ID)?;
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ThreadingModel {
+    /// No `ThreadingModel` value: the object lives in the main single-threaded apartment.
+    Single,
+    Apartment,
+    Free,
+    Both,
+    Neutral,
+    Other(String),
+}
 
-    let inproc = com_object.create_subkey(w!("InprocServer32"))?;
-    inproc.set_pcwstr(PCWSTR::null(), PCWSTR::from_raw(module_path.as_ptr()))?;
-    inproc.set_pcwstr(w!("ThreadingModel"), apartment_type)?;
+impl ThreadingModel {
+    /// The string stored in the registry, or `None` for [`ThreadingModel::Single`], which is
+    /// expressed by the value's absence.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Single => None,
+            Self::Apartment => Some("Apartment"),
+            Self::Free => Some("Free"),
+            Self::Both => Some("Both"),
+            Self::Neutral => Some("Neutral"),
+            Self::Other(value) => Some(value),
+        }
+    }
 
-    classes
-        .create_subkey(T::PROG_ID)?
-        .create_subkey(w!("CLSID"))?
-        .set_guid(PCWSTR::null(), &T::CLSID)?;
+    fn parse(value: &str) -> Self {
+        // COM treats an empty value like a missing one.
+        if value.is_empty() {
+            return Self::Single;
+        }
 
-    classes
-        .create_subkey(T::VERSION_INDEPENDENT_PROG_ID)?
-        .create_subkey(w!("CLSID"))?
-        .set_guid(PCWSTR::null(), &T::CLSID)?;
+        [
+            ("Apartment", Self::Apartment),
+            ("Free", Self::Free),
+            ("Both", Self::Both),
+            ("Neutral", Self::Neutral),
+        ]
+        .into_iter()
+        .find(|(name, _)| value.eq_ignore_ascii_case(name))
+        .map_or_else(|| Self::Other(value.to_owned()), |(_, model)| model)
+    }
+}
 
-    Ok(com_object)
+/// Reads the threading model of an in-process server, opening `InprocServer32` for reading only
+/// so classes the caller may not write can be audited too.
+pub fn read_threading_model(com_object: &Key) -> windows::core::Result<ThreadingModel> {
+    Ok(
+        match com_object
+            .open_subkey_readonly(w!("InprocServer32"))?
+            .get_str(w!("ThreadingModel"))?
+        {
+            Some(value) => ThreadingModel::parse(&value),
+            None => ThreadingModel::Single,
+        },
+    )
 }
 
-pub fn unregister_com_extension<T: CoClass>(classes: &Key) -> windows::core::Result<()> {
-    let mut buffer = [0u16; 39 + 6];
-    unsafe {
-        buffer[..6]
-            .as_mut_ptr()
-            .copy_from_nonoverlapping(w!("CLSID\\").as_ptr(), 6);
+/// Writes `model` so that [`read_threading_model`] reads it back unchanged.
+pub fn write_threading_model<K: RegistryWrite>(
+    com_object: &K,
+    model: &ThreadingModel,
+) -> windows::core::Result<()> {
+    let inproc = com_object.create_subkey(w!("InprocServer32"))?;
+
+    match model.as_str() {
+        Some(value) => inproc.set_str(w!("ThreadingModel"), value),
+        None => inproc.delete_value(w!("ThreadingModel")),
     }
+}
 
-    let clsid_string = T::CLSID.to_wide();
-    buffer[6..].copy_from_slice(&clsid_string);
-    classes.delete_subkey(PCWSTR::from_raw(buffer.as_ptr()))?;
+/// Checks that `prog_id` is at most 39 characters long, starts with a letter and otherwise only
+/// contains letters, digits and periods, as required by `CLSIDFromProgID`. A null `prog_id` is
+/// rejected as well; callers for which the ProgId is optional check for null first.
+pub fn validate_prog_id(prog_id: PCWSTR) -> windows::core::Result<()> {
+    if prog_id.is_null() {
+        return Err(windows::core::Error::new(
+            E_INVALIDARG,
+            "invalid ProgId: null",
+        ));
+    }
+
+    let prog_id = String::from_utf16(unsafe { prog_id.as_wide() })?;
+    let invalid = |reason: String| {
+        Err(windows::core::Error::new(
+            E_INVALIDARG,
+            format!("invalid ProgId {prog_id:?}: {reason}"),
+        ))
+    };
+
+    if prog_id.chars().count() > 39 {
+        return invalid("longer than 39 characters".to_owned());
+    }
+
+    let mut chars = prog_id.chars();
+
+    match chars.next() {
+        None => return invalid("empty".to_owned()),
+        Some(c) if !c.is_ascii_alphabetic() => {
+            return invalid(format!("must start with a letter, not {c:?}"));
+        }
+        Some(_) => {}
+    }
+
+    match chars.find(|&c| !c.is_ascii_alphanumeric() && c != '.') {
+        Some(c) => invalid(format!("contains {c:?}")),
+        None => Ok(()),
+    }
+}
+
+/// Checks that `prog_id` names a single key, so removing it can neither delete the classes key
+/// itself nor reach below another key. Unlike [`validate_prog_id`], this accepts ProgIds written
+/// before registration validated them, so those can still be removed.
+fn validate_prog_id_for_removal(prog_id: &[u16]) -> windows::core::Result<()> {
+    if prog_id.is_empty() || prog_id.contains(&u16::from(b'\\')) {
+        return Err(windows::core::Error::new(
+            E_INVALIDARG,
+            format!(
+                "invalid ProgId {:?}: empty or contains a backslash",
+                String::from_utf16_lossy(prog_id)
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Keeps the subkeys opened during a registration run, so keys shared between classes such as
+/// `CLSID` are opened once instead of once per class.
+pub struct KeyCache<'k, K> {
+    root: &'k K,
+    keys: RefCell<HashMap<String, Rc<K>>>,
+}
+
+impl<'k, K: RegistryWrite> KeyCache<'k, K> {
+    pub fn new(root: &'k K) -> Self {
+        Self {
+            root,
+            keys: RefCell::default(),
+        }
+    }
+
+    pub fn root(&self) -> &'k K {
+        self.root
+    }
+
+    /// Creates or opens `sub_key` below the root, reusing the handle from an earlier call. The
+    /// root itself is available through [`KeyCache::root`], so a null `sub_key` is rejected.
+    pub fn create_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Rc<K>> {
+        if sub_key.is_null() {
+            return Err(E_INVALIDARG.into());
+        }
+
+        // Registry paths are case-insensitive.
+        let path = String::from_utf16(unsafe { sub_key.as_wide() })?.to_lowercase();
+
+        if let Some(key) = self.keys.borrow().get(&path) {
+            return Ok(key.clone());
+        }
+
+        let key = Rc::new(self.root.create_subkey(sub_key)?);
+        self.keys.borrow_mut().insert(path, key.clone());
+        Ok(key)
+    }
+}
+
+/// The threading models an in-process server can declare. A server without one is
+/// [`ThreadingModel::Single`], which [`register_com_extension_with_threading_model`] can
+/// register by passing a null `ThreadingModel`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApartmentType {
+    Apartment,
+    Free,
+    Both,
+    Neutral,
+}
+
+impl ApartmentType {
+    pub fn as_pcwstr(self) -> PCWSTR {
+        match self {
+            Self::Apartment => w!("Apartment"),
+            Self::Free => w!("Free"),
+            Self::Both => w!("Both"),
+            Self::Neutral => w!("Neutral"),
+        }
+    }
+}
+
+impl From<ApartmentType> for ThreadingModel {
+    fn from(value: ApartmentType) -> Self {
+        match value {
+            ApartmentType::Apartment => Self::Apartment,
+            ApartmentType::Free => Self::Free,
+            ApartmentType::Both => Self::Both,
+            ApartmentType::Neutral => Self::Neutral,
+        }
+    }
+}
+
+/// How the class's server is started.
+#[derive(Clone, Copy)]
+pub enum ServerKind<'a> {
+    /// A DLL loaded into the client, registered as `InprocServer32`.
+    Inproc {
+        module_path: NullTerminatedSlice<'a>,
+        apartment_type: ApartmentType,
+    },
+    /// An EXE started by COM, registered as `LocalServer32`. Local servers have no
+    /// `ThreadingModel`.
+    LocalServer {
+        exe_path: &'a Path,
+        arguments: &'a OsStr,
+    },
+}
+
+/// Registers `T` below `classes`. `prog_id_friendly_name`, if not null, becomes the default value
+/// of the ProgId keys, which Explorer shows in its "Open with" list.
+///
+/// [`ComRegistration::register`] does the same for any [`RegistryWrite`] key, such as the
+/// [`DirectKey`](direct::DirectKey) of a loaded hive.
+pub fn register_com_extension<'a, T: CoClass>(
+    classes: &'a Key,
+    server: ServerKind,
+    description: PCWSTR,
+    prog_id_friendly_name: PCWSTR,
+) -> windows::core::Result<Key<'a>> {
+    ComRegistration::<T>::new(server)
+        .description(description)
+        .prog_id_friendly_name(prog_id_friendly_name)
+        .register(classes)
+}
+
+/// Like [`register_com_extension`], but shares opened keys with other registrations through
+/// `cache`.
+pub fn register_com_extension_cached<'a, T: CoClass>(
+    cache: &KeyCache<'_, Key<'a>>,
+    server: ServerKind,
+    description: PCWSTR,
+    prog_id_friendly_name: PCWSTR,
+) -> windows::core::Result<Key<'a>> {
+    ComRegistration::<T>::new(server)
+        .description(description)
+        .prog_id_friendly_name(prog_id_friendly_name)
+        .register_cached(cache)
+}
+
+/// Like [`register_com_extension`] for an in-process server, but writes `threading_model` as is,
+/// for values [`ApartmentType`] does not cover. A null `threading_model` leaves out the value,
+/// registering a single-threaded server.
+pub fn register_com_extension_with_threading_model<'a, T: CoClass>(
+    classes: &'a Key,
+    module_path: NullTerminatedSlice,
+    description: PCWSTR,
+    prog_id_friendly_name: PCWSTR,
+    threading_model: PCWSTR,
+) -> windows::core::Result<Key<'a>> {
+    ComRegistration::<T>::with_server(Server::InprocWithThreadingModel {
+        module_path,
+        threading_model,
+    })
+    .description(description)
+    .prog_id_friendly_name(prog_id_friendly_name)
+    .register(classes)
+}
+
+#[derive(Clone, Copy)]
+enum Server<'s> {
+    Kind(ServerKind<'s>),
+    InprocWithThreadingModel {
+        module_path: NullTerminatedSlice<'s>,
+        threading_model: PCWSTR,
+    },
+}
+
+/// The registration of a class `T`, with the optional parts defaulting to `T`'s constants.
+///
+/// ```ignore
+/// ComRegistration::<MyClass>::new(ServerKind::Inproc {
+///     module_path,
+///     apartment_type: ApartmentType::Both,
+/// })
+/// .description(w!("My class"))
+/// .default_icon(1)
+/// .register(&classes)?;
+/// ```
+#[derive(Clone, Copy)]
+pub struct ComRegistration<'s, T: CoClass> {
+    server: Server<'s>,
+    description: PCWSTR,
+    prog_id_friendly_name: PCWSTR,
+    type_lib: Option<GUID>,
+    default_icon: Option<i32>,
+    app_id: Option<(GUID, AppIdOptions)>,
+    _class: PhantomData<fn() -> T>,
+}
+
+impl<'s, T: CoClass> ComRegistration<'s, T> {
+    pub fn new(server: ServerKind<'s>) -> Self {
+        Self::with_server(Server::Kind(server))
+    }
+
+    fn with_server(server: Server<'s>) -> Self {
+        Self {
+            server,
+            description: PCWSTR::null(),
+            prog_id_friendly_name: PCWSTR::null(),
+            type_lib: T::TYPE_LIB,
+            default_icon: T::DEFAULT_ICON,
+            app_id: T::APP_ID.map(|app_id| (app_id, T::APP_ID_OPTIONS)),
+            _class: PhantomData,
+        }
+    }
+
+    /// Sets the default value of the CLSID key.
+    pub fn description(mut self, description: PCWSTR) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Sets the default value of the ProgId keys, which Explorer shows in its "Open with" list.
+    pub fn prog_id_friendly_name(mut self, prog_id_friendly_name: PCWSTR) -> Self {
+        self.prog_id_friendly_name = prog_id_friendly_name;
+        self
+    }
+
+    pub fn type_lib(mut self, type_lib: GUID) -> Self {
+        self.type_lib = Some(type_lib);
+        self
+    }
+
+    /// Sets the index of the icon in the server module, see [`CoClass::DEFAULT_ICON`].
+    pub fn default_icon(mut self, index: i32) -> Self {
+        self.default_icon = Some(index);
+        self
+    }
+
+    /// Hosts the class under `app_id`. [`unregister_com_extension`] only removes
+    /// [`CoClass::APP_ID`], so other AppIDs have to be removed with [`unregister_appid`].
+    pub fn app_id(mut self, app_id: GUID, options: AppIdOptions) -> Self {
+        self.app_id = Some((app_id, options));
+        self
+    }
+
+    pub fn register<K: RegistryWrite>(&self, classes: &K) -> windows::core::Result<K> {
+        self.register_cached(&KeyCache::new(classes))
+    }
+
+    /// Like [`ComRegistration::register`], but shares opened keys with other registrations
+    /// through `cache`.
+    pub fn register_cached<K: RegistryWrite>(
+        &self,
+        cache: &KeyCache<'_, K>,
+    ) -> windows::core::Result<K> {
+        let classes = cache.root();
+
+        // A null ProgId means the class is only activated by CLSID.
+        if !T::PROG_ID.is_null() {
+            validate_prog_id(T::PROG_ID)?;
+        }
+
+        validate_prog_id(T::VERSION_INDEPENDENT_PROG_ID)?;
+
+        #[cfg(feature = "log")]
+        log::debug!(
+            "registering {} ({})",
+            crate::com::GuidDisplay(&T::CLSID),
+            String::from_utf16_lossy(unsafe { T::VERSION_INDEPENDENT_PROG_ID.as_wide() })
+        );
+
+        let clsid_string = T::CLSID.to_wide();
+        let com_object = cache
+            .create_subkey(w!("CLSID"))?
+            .create_subkey(PCWSTR::from_raw(clsid_string.as_ptr()))?;
+
+        com_object.set_default_pcwstr(self.description)?;
+
+        if !T::PROG_ID.is_null() {
+            com_object
+                .create_subkey(w!("ProgId"))?
+                .set_default_pcwstr(T::PROG_ID)?;
+        }
+
+        com_object
+            .create_subkey(w!("VersionIndependentProgId"))?
+            .set_default_pcwstr(T::VERSION_INDEPENDENT_PROG_ID)?;
+
+        if !T::IMPLEMENTED_CATEGORIES.is_empty() {
+            let categories = com_object.create_subkey(w!("Implemented Categories"))?;
+
+            for catid in T::IMPLEMENTED_CATEGORIES {
+                categories.create_subkey(PCWSTR::from_raw(catid.to_wide().as_ptr()))?;
+            }
+        }
+
+        if let Some(type_lib) = &self.type_lib {
+            com_object
+                .create_subkey(w!("TypeLib"))?
+                .set_default_guid(type_lib)?;
+        }
+
+        let server_path = self.write_server(&com_object)?;
+
+        if let Some((app_id, options)) = &self.app_id {
+            register_appid(
+                classes,
+                &T::CLSID,
+                app_id,
+                options.dll_surrogate,
+                options.run_as,
+            )?;
+        }
+
+        let default_icon = self.default_icon.map(|index| {
+            let mut icon = server_path;
+            icon.push(format!(",{index}"));
+            icon
+        });
+
+        for prog_id in [T::PROG_ID, T::VERSION_INDEPENDENT_PROG_ID] {
+            // Only the versioned ProgId may be null, the other one was validated above.
+            if prog_id.is_null() {
+                continue;
+            }
+
+            let prog_id = classes.create_subkey(prog_id)?;
+
+            if !self.prog_id_friendly_name.is_null() {
+                prog_id.set_default_pcwstr(self.prog_id_friendly_name)?;
+            }
+
+            prog_id
+                .create_subkey(w!("CLSID"))?
+                .set_default_guid(&T::CLSID)?;
+
+            if let Some(default_icon) = &default_icon {
+                prog_id
+                    .create_subkey(w!("DefaultIcon"))?
+                    .set_os_str(PCWSTR::null(), default_icon)?;
+            }
+        }
+
+        if !T::PROG_ID.is_null() {
+            classes
+                .create_subkey(T::VERSION_INDEPENDENT_PROG_ID)?
+                .create_subkey(w!("CurVer"))?
+                .set_default_pcwstr(T::PROG_ID)?;
+        }
+
+        Ok(com_object)
+    }
+
+    /// Writes the server subkey and returns the path of the server module.
+    fn write_server<K: RegistryWrite>(&self, com_object: &K) -> windows::core::Result<OsString> {
+        match self.server {
+            Server::Kind(ServerKind::Inproc {
+                module_path,
+                apartment_type,
+            }) => write_inproc_server(com_object, module_path, apartment_type.as_pcwstr()),
+            Server::InprocWithThreadingModel {
+                module_path,
+                threading_model,
+            } => write_inproc_server(com_object, module_path, threading_model),
+            Server::Kind(ServerKind::LocalServer {
+                exe_path,
+                arguments,
+            }) => {
+                let mut command_line = OsString::from("\"");
+                command_line.push(exe_path);
+                command_line.push("\"");
+
+                if !arguments.is_empty() {
+                    command_line.push(" ");
+                    command_line.push(arguments);
+                }
+
+                com_object
+                    .create_subkey(w!("LocalServer32"))?
+                    .set_os_str(PCWSTR::null(), &command_line)?;
+
+                Ok(exe_path.as_os_str().to_owned())
+            }
+        }
+    }
+}
+
+fn write_inproc_server<K: RegistryWrite>(
+    com_object: &K,
+    module_path: NullTerminatedSlice,
+    threading_model: PCWSTR,
+) -> windows::core::Result<OsString> {
+    let inproc = com_object.create_subkey(w!("InprocServer32"))?;
+    inproc.set_default_pcwstr(PCWSTR::from_raw(module_path.as_ptr()))?;
+
+    if !threading_model.is_null() {
+        inproc.set_pcwstr(w!("ThreadingModel"), threading_model)?;
+    }
+
+    Ok(OsString::from_wide(&module_path[..module_path.len() - 1]))
+}
+
+/// Runs `register` in `transaction`, then creates an instance of `T` through the
+/// [`CoClassFactory`] the DLL serves it from and only commits if that succeeds.
+///
+/// `CoCreateInstance` only sees committed registrations, so the smoke test calls the class
+/// factory directly rather than going through the registry. It catches failing constructors,
+/// not mistakes in the registration itself.
+pub fn register_and_smoke_test<T: CreatableCoClass + Into<IUnknown> + 'static>(
+    transaction: Transaction,
+    register: impl FnOnce(&Transaction) -> windows::core::Result<()>,
+) -> windows::core::Result<()> {
+    register(&transaction)?;
+
+    let factory: IClassFactory = CoClassFactory::<T>::new().into();
+
+    // Dropping the transaction on failure rolls the registration back.
+    unsafe { factory.CreateInstance::<_, IUnknown>(None)? };
+    transaction.commit()
+}
+
+/// Writes the `OLEMISC` flags of an OLE object: `default_flags` as the `MiscStatus` default and
+/// each `(aspect, flags)` pair as a `MiscStatus\<aspect>` subkey. OLE reads these as decimal
+/// strings, not `REG_DWORD`s.
+pub fn set_misc_status<K: RegistryWrite>(
+    com_object: &K,
+    default_flags: u32,
+    aspect_flags: &[(u32, u32)],
+) -> windows::core::Result<()> {
+    let misc_status = com_object.create_subkey(w!("MiscStatus"))?;
+    misc_status.set_default_str(&default_flags.to_string())?;
+
+    for (aspect, flags) in aspect_flags {
+        misc_status
+            .create_subkey(PCWSTR::from_raw(raw::to_wide(&aspect.to_string()).as_ptr()))?
+            .set_default_str(&flags.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Records the version of the registering binary as the `Version` value of the class key.
+pub fn set_server_version<K: RegistryWrite>(
+    com_object: &K,
+    version: PCWSTR,
+) -> windows::core::Result<()> {
+    com_object.set_pcwstr(w!("Version"), version)
+}
+
+/// Sets the `AppUserModelID` of a ProgId so taskbar grouping and jump lists pick it up.
+pub fn set_app_user_model_id<K: RegistryWrite>(
+    prog_id: &K,
+    aumid: PCWSTR,
+) -> windows::core::Result<()> {
+    prog_id.set_pcwstr(w!("AppUserModelID"), aumid)
+}
+
+/// Builds the nul-terminated path `parent\{guid}`.
+fn guid_path(parent: &str, guid: &GUID) -> Vec<u16> {
+    parent
+        .encode_utf16()
+        .chain(std::iter::once(u16::from(b'\\')))
+        .chain(guid.to_wide())
+        .collect()
+}
+
+// Taking `impl RegistryWrite` keeps `unregister_com_extension::<T>(...)` callable with only `T`.
+pub fn unregister_com_extension<T: CoClass>(
+    classes: &impl RegistryWrite,
+) -> windows::core::Result<()> {
+    // Deleting a null or empty subkey would delete the classes key itself, so those mean there is
+    // no ProgId to remove, and the others are checked before anything is deleted.
+    let prog_ids = [T::PROG_ID, T::VERSION_INDEPENDENT_PROG_ID]
+        .into_iter()
+        .filter(|prog_id| !prog_id.is_null() && !unsafe { prog_id.as_wide() }.is_empty())
+        .collect::<Vec<_>>();
+
+    for prog_id in &prog_ids {
+        validate_prog_id_for_removal(unsafe { prog_id.as_wide() })?;
+    }
+
+    #[cfg(feature = "log")]
+    log::debug!(
+        "unregistering {} ({:?})",
+        crate::com::GuidDisplay(&T::CLSID),
+        prog_ids
+            .iter()
+            .map(|prog_id| String::from_utf16_lossy(unsafe { prog_id.as_wide() }))
+            .collect::<Vec<_>>()
+    );
+
+    // Also removes the server, TypeLib and Implemented Categories subkeys.
+    let path = guid_path("CLSID", &T::CLSID);
+    classes.delete_subkey(PCWSTR::from_raw(path.as_ptr()))?;
+
+    for prog_id in prog_ids {
+        classes.delete_subkey(prog_id)?;
+    }
+
+    if let Some(appid) = &T::APP_ID {
+        let path = guid_path("AppID", appid);
+        classes.delete_subkey(PCWSTR::from_raw(path.as_ptr()))?;
+    }
+
+    Ok(())
+}
+
+/// The optional values written to `AppID\{appid}`, as described on [`register_appid`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AppIdOptions {
+    pub dll_surrogate: Option<PCWSTR>,
+    pub run_as: Option<PCWSTR>,
+}
+
+impl AppIdOptions {
+    pub const fn new() -> Self {
+        Self {
+            dll_surrogate: None,
+            run_as: None,
+        }
+    }
+}
+
+/// Creates `AppID\{appid}` and links the class to it through its `AppID` value.
+///
+/// `dll_surrogate` hosts an in-process server out of process, with an empty string selecting
+/// the system surrogate `dllhost.exe`. `run_as` sets the identity the server runs as, such as
+/// `Interactive User` or an account name.
+pub fn register_appid<K: RegistryWrite>(
+    classes: &K,
+    clsid: &GUID,
+    appid: &GUID,
+    dll_surrogate: Option<PCWSTR>,
+    run_as: Option<PCWSTR>,
+) -> windows::core::Result<K> {
+    let path = guid_path("AppID", appid);
+    let app = classes.create_subkey(PCWSTR::from_raw(path.as_ptr()))?;
+
+    if let Some(dll_surrogate) = dll_surrogate {
+        app.set_pcwstr(w!("DllSurrogate"), dll_surrogate)?;
+    }
+
+    if let Some(run_as) = run_as {
+        app.set_pcwstr(w!("RunAs"), run_as)?;
+    }
+
+    let path = guid_path("CLSID", clsid);
+    classes
+        .create_subkey(PCWSTR::from_raw(path.as_ptr()))?
+        .set_guid(w!("AppID"), appid)?;
+
+    Ok(app)
+}
+
+/// Removes `AppID\{appid}`, including its `RunAs` identity, and the class's link to it.
+pub fn unregister_appid<K: RegistryWrite>(
+    classes: &K,
+    clsid: &GUID,
+    appid: &GUID,
+) -> windows::core::Result<()> {
+    let path = guid_path("AppID", appid);
+    classes.delete_subkey(PCWSTR::from_raw(path.as_ptr()))?;
+
+    let path = guid_path("CLSID", clsid);
+
+    match classes.try_open_subkey(PCWSTR::from_raw(path.as_ptr()))? {
+        Some(class) => class.delete_value(w!("AppID")),
+        None => Ok(()),
+    }
+}
+
+/// Makes OLE convert embedded objects of `old_clsid` to `new_clsid` when they are loaded, unlike
+/// `TreatAs`, which only emulates the old class.
+pub fn register_auto_convert_to<K: RegistryWrite>(
+    classes: &K,
+    old_clsid: &GUID,
+    new_clsid: &GUID,
+) -> windows::core::Result<()> {
+    let path = guid_path("CLSID", old_clsid);
+
+    classes
+        .create_subkey(PCWSTR::from_raw(path.as_ptr()))?
+        .create_subkey(w!("AutoConvertTo"))?
+        .set_default_guid(new_clsid)
+}
+
+pub fn unregister_auto_convert_to<K: RegistryWrite>(
+    classes: &K,
+    old_clsid: &GUID,
+) -> windows::core::Result<()> {
+    let path = guid_path("CLSID", old_clsid);
+
+    match classes.try_open_subkey(PCWSTR::from_raw(path.as_ptr()))? {
+        Some(class) => class.delete_subkey(w!("AutoConvertTo")),
+        None => Ok(()),
+    }
+}
+
+/// A class to remove with [`unregister_manifest`], typically read back from an install log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UninstallEntry {
+    pub clsid: GUID,
+    pub prog_ids: Vec<String>,
+}
+
+/// Removes the `CLSID\{clsid}` tree and ProgId keys of every entry, skipping ones that are
+/// already gone.
+pub fn unregister_manifest<K: RegistryWrite>(
+    classes: &K,
+    entries: &[UninstallEntry],
+) -> windows::core::Result<()> {
+    // Deleting an empty subkey would delete the classes key itself, so check everything first.
+    for prog_id in entries.iter().flat_map(|entry| &entry.prog_ids) {
+        validate_prog_id_for_removal(&prog_id.encode_utf16().collect::<Vec<_>>())?;
+    }
+
+    for entry in entries {
+        let path = guid_path("CLSID", &entry.clsid);
+        classes.delete_subkey(PCWSTR::from_raw(path.as_ptr()))?;
+
+        for prog_id in &entry.prog_ids {
+            classes.delete_subkey(PCWSTR::from_raw(raw::to_wide(prog_id).as_ptr()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns every top-level ProgId whose `CLSID` subkey points at `clsid`.
+pub fn prog_ids_for_clsid(classes: &Key, clsid: &GUID) -> windows::core::Result<Vec<String>> {
+    let mut prog_ids = Vec::new();
+
+    for name in classes.subkeys()? {
+        let path = raw::to_wide(&raw::join_path(&name, "CLSID"));
+
+        // Extensions, CLSID, Interface and the like have no CLSID subkey. Only reading is
+        // needed, and classes the caller may not even read are skipped.
+        let key = match classes.open_subkey_readonly(PCWSTR::from_raw(path.as_ptr())) {
+            Ok(key) => key,
+            Err(RegistryError::NotFound(_) | RegistryError::AccessDenied(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // Skip malformed entries rather than failing the whole scan.
+        let Ok(Some(value)) = key.get_str(PCWSTR::null()) else {
+            continue;
+        };
+
+        if GUID::from_str(&value).is_ok_and(|value| value == *clsid) {
+            prog_ids.push(name);
+        }
+    }
+
+    Ok(prog_ids)
+}
+
+/// `PSOAInterface`, the typelib-driven universal marshaler from `oleaut32.dll`.
+pub const OLEAUT_PROXY_STUB_CLSID: GUID = GUID::from_u128(0x00020424_0000_0000_c000_000000000046);
+
+/// Creates `Interface\{iid}`, marshaled by the proxy/stub class `proxy_stub_clsid`.
+pub fn register_interface<K: RegistryWrite>(
+    classes: &K,
+    iid: &GUID,
+    name: PCWSTR,
+    proxy_stub_clsid: &GUID,
+    num_methods: Option<u32>,
+) -> windows::core::Result<K> {
+    let iid_string = iid.to_wide();
+    let interface = classes
+        .create_subkey(w!("Interface"))?
+        .create_subkey(PCWSTR::from_raw(iid_string.as_ptr()))?;
+
+    interface.set_default_pcwstr(name)?;
+
+    interface
+        .create_subkey(w!("ProxyStubClsid32"))?
+        .set_default_guid(proxy_stub_clsid)?;
+
+    if let Some(num_methods) = num_methods {
+        interface
+            .create_subkey(w!("NumMethods"))?
+            .set_default_str(&num_methods.to_string())?;
+    }
+
+    Ok(interface)
+}
+
+pub fn register_oleaut_marshaled_interface<K: RegistryWrite>(
+    classes: &K,
+    iid: &GUID,
+    name: PCWSTR,
+    typelib: &GUID,
+) -> windows::core::Result<K> {
+    let interface = register_interface(classes, iid, name, &OLEAUT_PROXY_STUB_CLSID, None)?;
+
+    interface
+        .create_subkey(w!("ProxyStubClsid"))?
+        .set_default_guid(&OLEAUT_PROXY_STUB_CLSID)?;
+
+    interface
+        .create_subkey(w!("TypeLib"))?
+        .set_default_guid(typelib)?;
+
+    Ok(interface)
+}
+
+pub fn unregister_interface<K: RegistryWrite>(
+    classes: &K,
+    iid: &GUID,
+) -> windows::core::Result<()> {
+    let path = guid_path("Interface", iid);
+    classes.delete_subkey(PCWSTR::from_raw(path.as_ptr()))
+}
+
+/// Points `ShellEx\{category}` below `prog_id` at `handler_clsid`, the layout used by icon
+/// handlers, thumbnail providers, property handlers and similar shell extensions.
+pub fn register_shellex_handler<K: RegistryWrite>(
+    prog_id: &K,
+    handler_category_iid: &GUID,
+    handler_clsid: &GUID,
+) -> windows::core::Result<()> {
+    let category = handler_category_iid.to_wide();
+
+    prog_id
+        .create_subkey(w!("ShellEx"))?
+        .create_subkey(PCWSTR::from_raw(category.as_ptr()))?
+        .set_default_guid(handler_clsid)
+}
+
+pub fn unregister_shellex_handler<K: RegistryWrite>(
+    prog_id: &K,
+    handler_category_iid: &GUID,
+) -> windows::core::Result<()> {
+    let path = guid_path("ShellEx", handler_category_iid);
+    prog_id.delete_subkey(PCWSTR::from_raw(path.as_ptr()))
+}
+
+/// `IPreviewHandler`, the `ShellEx` category preview handlers are registered under.
+pub const PREVIEW_HANDLER_CATEGORY: GUID = GUID::from_u128(0x8895b1c6_b41f_4c1c_a562_0d564250836f);
+
+/// Registers `handler_clsid` as the preview handler of `file_prog_id` and adds it to the list of
+/// preview handlers below `software`. That list is not part of the classes key, so `software`
+/// should be the `Software` key `classes` belongs to, such as `HKEY_CURRENT_USER\Software` for
+/// per-user registrations.
+pub fn register_preview_handler<K: RegistryWrite>(
+    classes: &K,
+    software: &K,
+    file_prog_id: PCWSTR,
+    handler_clsid: &GUID,
+    display_name: PCWSTR,
+) -> windows::core::Result<()> {
+    // An empty ProgId would put the handler directly below the classes key.
+    if file_prog_id.is_null() || unsafe { file_prog_id.as_wide() }.is_empty() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    register_shellex_handler(
+        &classes.create_subkey(file_prog_id)?,
+        &PREVIEW_HANDLER_CATEGORY,
+        handler_clsid,
+    )?;
+
+    let handler_clsid = handler_clsid.to_wide();
+    software
+        .create_subkey(w!("Microsoft\\Windows\\CurrentVersion\\PreviewHandlers"))?
+        .set_pcwstr(PCWSTR::from_raw(handler_clsid.as_ptr()), display_name)
+}
+
+/// `IThumbnailProvider`, the `ShellEx` category thumbnail providers are registered under.
+pub const THUMBNAIL_PROVIDER_CATEGORY: GUID =
+    GUID::from_u128(0xe357fccd_a995_4576_b01f_234630154e96);
+
+/// Rejects a null or empty name, which would open the classes key itself.
+fn validate_extension_or_prog_id(extension_or_prog_id: PCWSTR) -> windows::core::Result<()> {
+    if extension_or_prog_id.is_null() || unsafe { extension_or_prog_id.as_wide() }.is_empty() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    Ok(())
+}
+
+/// Registers `handler_clsid` as the thumbnail provider of `extension_or_prog_id`, which may be
+/// a file extension including its leading period or a ProgId.
+pub fn register_thumbnail_provider<K: RegistryWrite>(
+    classes: &K,
+    extension_or_prog_id: PCWSTR,
+    handler_clsid: &GUID,
+) -> windows::core::Result<()> {
+    validate_extension_or_prog_id(extension_or_prog_id)?;
+
+    register_shellex_handler(
+        &classes.create_subkey(extension_or_prog_id)?,
+        &THUMBNAIL_PROVIDER_CATEGORY,
+        handler_clsid,
+    )
+}
+
+pub fn unregister_thumbnail_provider<K: RegistryWrite>(
+    classes: &K,
+    extension_or_prog_id: PCWSTR,
+) -> windows::core::Result<()> {
+    validate_extension_or_prog_id(extension_or_prog_id)?;
+
+    match classes.try_open_subkey(extension_or_prog_id)? {
+        Some(key) => unregister_shellex_handler(&key, &THUMBNAIL_PROVIDER_CATEGORY),
+        None => Ok(()),
+    }
+}
+
+/// The path of the context menu handlers below a ProgId, `*` or `Directory` key.
+pub const CONTEXT_MENU_HANDLERS: PCWSTR = w!("ShellEx\\ContextMenuHandlers");
+
+/// Registers `handler_clsid` as the context menu handler `name` of `target`, such as a ProgId,
+/// `*` for all files or `Directory`.
+pub fn register_context_menu_handler<K: RegistryWrite>(
+    target: &K,
+    name: PCWSTR,
+    handler_clsid: &GUID,
+) -> windows::core::Result<()> {
+    if name.is_null() || unsafe { name.as_wide() }.is_empty() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    target
+        .create_subkey(CONTEXT_MENU_HANDLERS)?
+        .create_subkey(name)?
+        .set_default_guid(handler_clsid)
+}
+
+/// Removes only the handler `name`, leaving those of other applications in place.
+pub fn unregister_context_menu_handler<K: RegistryWrite>(
+    target: &K,
+    name: PCWSTR,
+) -> windows::core::Result<()> {
+    if name.is_null() || unsafe { name.as_wide() }.is_empty() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    match target.try_open_subkey(CONTEXT_MENU_HANDLERS)? {
+        Some(handlers) => handlers.delete_subkey(name),
+        None => Ok(()),
+    }
+}
+
+const PROPERTY_HANDLERS: PCWSTR =
+    w!("Software\\Microsoft\\Windows\\CurrentVersion\\PropertySystem\\PropertyHandlers");
+
+/// Registers `handler_clsid` as the Windows Search property handler for the extension `ext`,
+/// including its leading period. Unlike shell extensions these live under
+/// `HKEY_LOCAL_MACHINE` only, so `machine_root` should be the root of that hive.
+pub fn register_property_handler<K: RegistryWrite>(
+    machine_root: &K,
+    ext: PCWSTR,
+    handler_clsid: &GUID,
+) -> windows::core::Result<()> {
+    if ext.is_null() || unsafe { ext.as_wide() }.is_empty() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    machine_root
+        .create_subkey(PROPERTY_HANDLERS)?
+        .create_subkey(ext)?
+        .set_default_guid(handler_clsid)
+}
+
+pub fn unregister_property_handler<K: RegistryWrite>(
+    machine_root: &K,
+    ext: PCWSTR,
+) -> windows::core::Result<()> {
+    if ext.is_null() || unsafe { ext.as_wide() }.is_empty() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    match machine_root.try_open_subkey(PROPERTY_HANDLERS)? {
+        Some(handlers) => handlers.delete_subkey(ext),
+        None => Ok(()),
+    }
+}
+
+const APPROVED_SHELL_EXTENSIONS: PCWSTR =
+    w!("Software\\Microsoft\\Windows\\CurrentVersion\\Shell Extensions\\Approved");
+
+/// Lists `T` under `Shell Extensions\Approved`, without which Explorer ignores the extension
+/// when it enforces the approved list.
+pub fn register_approved_extension<T: CoClass>(
+    transaction: &Transaction,
+    friendly_name: PCWSTR,
+) -> windows::core::Result<()> {
+    let clsid = T::CLSID.to_wide();
+    Key::predefined(transaction, HKEY_LOCAL_MACHINE, APPROVED_SHELL_EXTENSIONS)?
+        .set_pcwstr(PCWSTR::from_raw(clsid.as_ptr()), friendly_name)
+}
+
+pub fn unregister_approved_extension<T: CoClass>(
+    transaction: &Transaction,
+) -> windows::core::Result<()> {
+    let clsid = T::CLSID.to_wide();
+    Key::predefined(transaction, HKEY_LOCAL_MACHINE, APPROVED_SHELL_EXTENSIONS)?
+        .delete_value(PCWSTR::from_raw(clsid.as_ptr()))
+}
+
+fn validate_extension(extension: PCWSTR) -> windows::core::Result<()> {
+    if extension.is_null() || unsafe { extension.as_wide() }.first() != Some(&u16::from(b'.')) {
+        return Err(E_INVALIDARG.into());
+    }
+
+    Ok(())
+}
+
+/// Makes `prog_id` the default handler of `extension`, including its leading period, and lists
+/// it under `OpenWithProgids`.
+pub fn associate_extension<K: RegistryWrite>(
+    classes: &K,
+    extension: PCWSTR,
+    prog_id: PCWSTR,
+) -> windows::core::Result<()> {
+    validate_extension(extension)?;
+    validate_prog_id(prog_id)?;
+
+    let extension = classes.create_subkey(extension)?;
+    extension.set_default_pcwstr(prog_id)?;
+    extension
+        .create_subkey(w!("OpenWithProgids"))?
+        .set_none(prog_id)
+}
+
+/// Removes `prog_id` from `extension`'s `OpenWithProgids` and clears the default handler if it
+/// is still `prog_id`. The extension key itself is left alone, as other applications may have
+/// registered under it.
+pub fn unassociate_extension<K: RegistryWrite>(
+    classes: &K,
+    extension: PCWSTR,
+    prog_id: PCWSTR,
+) -> windows::core::Result<()> {
+    validate_extension(extension)?;
+
+    if prog_id.is_null() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    validate_prog_id_for_removal(unsafe { prog_id.as_wide() })?;
+
+    let Some(extension) = classes.try_open_subkey(extension)? else {
+        return Ok(());
+    };
+
+    if let Some(open_with) = extension.try_open_subkey(w!("OpenWithProgids"))? {
+        open_with.delete_value(prog_id)?;
+    }
+
+    let prog_id = String::from_utf16_lossy(unsafe { prog_id.as_wide() });
+
+    if extension
+        .get_str(PCWSTR::null())?
+        .is_some_and(|default| default.eq_ignore_ascii_case(&prog_id))
+    {
+        extension.delete_value(PCWSTR::null())?;
+    }
 
-    classes.delete_subkey(T::PROG_ID)?;
-    classes.delete_subkey(T::VERSION_INDEPENDENT_PROG_ID)?;
     Ok(())
 }
+
+/// Creates `shell\<verb>\command` below a ProgId with `command` as the command line, which
+/// should quote the executable and pass the file as `"%1"`. `display_name` is shown in the
+/// context menu in place of the verb.
+pub fn register_shell_verb<K: RegistryWrite>(
+    prog_id_key: &K,
+    verb: PCWSTR,
+    command: PCWSTR,
+    display_name: Option<PCWSTR>,
+) -> windows::core::Result<()> {
+    if verb.is_null() || unsafe { verb.as_wide() }.is_empty() {
+        return Err(E_INVALIDARG.into());
+    }
+
+    let verb = prog_id_key
+        .create_subkey(w!("shell"))?
+        .create_subkey(verb)?;
+
+    if let Some(display_name) = display_name {
+        verb.set_default_pcwstr(display_name)?;
+    }
+
+    verb.create_subkey(w!("command"))?
+        .set_default_pcwstr(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::{
+        Foundation::{ERROR_FILE_NOT_FOUND, WIN32_ERROR},
+        Security::{
+            ACL, ACL_REVISION, AddAccessAllowedAce, CreateWellKnownSid, DACL_SECURITY_INFORMATION,
+            InitializeAcl, InitializeSecurityDescriptor, PSECURITY_DESCRIPTOR, PSID,
+            SECURITY_DESCRIPTOR, SECURITY_MAX_SID_SIZE, SetSecurityDescriptorDacl, WinWorldSid,
+        },
+        Storage::FileSystem::DELETE,
+        System::Registry::{HKEY_CURRENT_USER, KEY_READ, KEY_SET_VALUE, REG_SZ},
+    };
+
+    use super::{ops::RegOp, *};
+
+    /// Opens a scratch key below `HKEY_CURRENT_USER\Software` within `transaction`, which
+    /// removes it again when the transaction is rolled back on drop.
+    fn scratch_key<'a>(transaction: &'a Transaction, name: &str) -> Key<'a> {
+        let path = scratch_path(name);
+        Key::predefined(
+            transaction,
+            HKEY_CURRENT_USER,
+            PCWSTR::from_raw(path.as_ptr()),
+        )
+        .unwrap()
+    }
+
+    fn scratch_path(name: &str) -> Vec<u16> {
+        // Each test gets its own key directly below `Software`, as transactions creating the same
+        // parent key in parallel would conflict.
+        raw::to_wide(&format!("Software\\registry-classes-utils-test-{name}"))
+    }
+
+    fn test_transaction() -> Transaction {
+        Transaction::new(w!("registry-classes-utils tests"), false).unwrap()
+    }
+
+    struct TestClass;
+
+    impl CoClass for TestClass {
+        const CLSID: GUID = GUID::from_u128(0x3f2c1d8e_5a47_4b6e_9c0d_1e2f3a4b5c6d);
+        const PROG_ID: PCWSTR = w!("RegistryClassesUtils.Test.1");
+        const VERSION_INDEPENDENT_PROG_ID: PCWSTR = w!("RegistryClassesUtils.Test");
+    }
+
+    fn create_key(path: &str) -> (HKEY, RegOp) {
+        (
+            HKEY_LOCAL_MACHINE,
+            RegOp::CreateKey {
+                path: format!("Software\\Classes\\{path}"),
+            },
+        )
+    }
+
+    fn set_default(path: &str, value: &[u16]) -> (HKEY, RegOp) {
+        set_str(path, "", value)
+    }
+
+    fn set_str(path: &str, name: &str, value: &[u16]) -> (HKEY, RegOp) {
+        (
+            HKEY_LOCAL_MACHINE,
+            RegOp::SetValue {
+                path: format!("Software\\Classes\\{path}"),
+                name: name.encode_utf16().collect(),
+                value_type: REG_SZ,
+                data: value.iter().flat_map(|c| c.to_le_bytes()).collect(),
+            },
+        )
+    }
+
+    fn wide(value: &str) -> Vec<u16> {
+        raw::to_wide(value)
+    }
+
+    /// Calls `f` with a security descriptor whose DACL grants Everyone only `access`.
+    fn with_dacl(access: u32, f: impl FnOnce(PSECURITY_DESCRIPTOR)) {
+        // Defined in Win32::System::SystemServices, which is not worth enabling for a test.
+        const SECURITY_DESCRIPTOR_REVISION: u32 = 1;
+
+        let mut sid_buffer = [0u8; SECURITY_MAX_SID_SIZE as usize];
+        let mut sid_size = sid_buffer.len() as u32;
+        let sid = PSID(sid_buffer.as_mut_ptr().cast());
+        let mut acl_buffer = [0u32; 64];
+        let acl = acl_buffer.as_mut_ptr().cast::<ACL>();
+        let mut descriptor = SECURITY_DESCRIPTOR::default();
+        let descriptor = PSECURITY_DESCRIPTOR((&raw mut descriptor).cast());
+
+        unsafe {
+            CreateWellKnownSid(WinWorldSid, None, Some(sid), &raw mut sid_size).unwrap();
+            InitializeAcl(acl, size_of_val(&acl_buffer) as u32, ACL_REVISION).unwrap();
+            AddAccessAllowedAce(acl, ACL_REVISION, access, sid).unwrap();
+            InitializeSecurityDescriptor(descriptor, SECURITY_DESCRIPTOR_REVISION).unwrap();
+            SetSecurityDescriptorDacl(descriptor, true, Some(acl), false).unwrap();
+        }
+
+        f(descriptor);
+    }
+
+    #[test]
+    fn dry_run_records_registration_layout() {
+        let module_path = raw::to_wide("C:\\test.dll");
+        let transaction = Transaction::dry_run(false);
+        let classes = Key::classes_root(&transaction, RegistrationScope::PerMachine).unwrap();
+
+        register_com_extension::<TestClass>(
+            &classes,
+            ServerKind::Inproc {
+                module_path: NullTerminatedSlice::new(&module_path).unwrap(),
+                apartment_type: ApartmentType::Both,
+            },
+            w!("Test class"),
+            PCWSTR::null(),
+        )
+        .unwrap();
+
+        let clsid = "CLSID\\{3f2c1d8e-5a47-4b6e-9c0d-1e2f3a4b5c6d}";
+        let clsid_value = TestClass::CLSID.to_wide();
+        let prog_id = "RegistryClassesUtils.Test.1";
+        let vi_prog_id = "RegistryClassesUtils.Test";
+
+        assert_eq!(
+            transaction.recorded_ops(),
+            [
+                (
+                    HKEY_LOCAL_MACHINE,
+                    RegOp::CreateKey {
+                        path: "Software\\Classes".to_owned(),
+                    },
+                ),
+                create_key("CLSID"),
+                create_key(clsid),
+                set_default(clsid, &wide("Test class")),
+                create_key(&format!("{clsid}\\ProgId")),
+                set_default(&format!("{clsid}\\ProgId"), &wide(prog_id)),
+                create_key(&format!("{clsid}\\VersionIndependentProgId")),
+                set_default(
+                    &format!("{clsid}\\VersionIndependentProgId"),
+                    &wide(vi_prog_id)
+                ),
+                create_key(&format!("{clsid}\\InprocServer32")),
+                set_default(&format!("{clsid}\\InprocServer32"), &wide("C:\\test.dll")),
+                set_str(
+                    &format!("{clsid}\\InprocServer32"),
+                    "ThreadingModel",
+                    &wide("Both"),
+                ),
+                create_key(prog_id),
+                create_key(&format!("{prog_id}\\CLSID")),
+                set_default(&format!("{prog_id}\\CLSID"), &clsid_value),
+                create_key(vi_prog_id),
+                create_key(&format!("{vi_prog_id}\\CLSID")),
+                set_default(&format!("{vi_prog_id}\\CLSID"), &clsid_value),
+                create_key(vi_prog_id),
+                create_key(&format!("{vi_prog_id}\\CurVer")),
+                set_default(&format!("{vi_prog_id}\\CurVer"), &wide(prog_id)),
+            ]
+        );
+
+        // Dropping an uncommitted dry run must not try to roll back a transaction that was never
+        // created.
+        drop(classes);
+        drop(transaction);
+    }
+
+    #[test]
+    fn multi_str_expand_round_trips() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "multi_str_expand_round_trips");
+        let entries = ["%SystemRoot%\\System32", "C:\\Tools", "%SystemRoot%"];
+
+        key.set_multi_str_expand(w!("Path"), &entries).unwrap();
+
+        assert_eq!(
+            key.get_multi_str_expand(w!("Path"), false).unwrap(),
+            Some(entries.map(str::to_owned).to_vec())
+        );
+
+        let system_root = std::env::var("SystemRoot").unwrap();
+
+        assert_eq!(
+            key.get_multi_str_expand(w!("Path"), true).unwrap(),
+            Some(vec![
+                format!("{system_root}\\System32"),
+                "C:\\Tools".to_owned(),
+                system_root,
+            ])
+        );
+
+        assert_eq!(key.get_multi_str_expand(w!("Missing"), true).unwrap(), None);
+    }
+
+    #[test]
+    fn multi_str_expand_rejects_unreadable_entries() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "multi_str_expand_rejects_unreadable_entries");
+
+        for entries in [&["a", "", "b"][..], &["a;b"][..]] {
+            let error = key.set_multi_str_expand(w!("Path"), entries).unwrap_err();
+            assert_eq!(error.code(), E_INVALIDARG);
+        }
+
+        assert!(!key.value_exists(w!("Path")).unwrap());
+    }
+
+    #[test]
+    fn keys_stay_usable_across_commit() {
+        let path = scratch_path("keys_stay_usable_across_commit");
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "keys_stay_usable_across_commit");
+
+        key.set_u32(w!("Value"), 1).unwrap();
+        transaction.commit().unwrap();
+
+        // The handle is still open, but the transaction it was opened in is over.
+        assert!(key.set_u32(w!("Value"), 2).is_err());
+        drop(key);
+        drop(transaction);
+
+        let committed = read::ReadKey::open(HKEY_CURRENT_USER, PCWSTR::from_raw(path.as_ptr()));
+        assert_eq!(committed.unwrap().get_u32(w!("Value")).unwrap(), Some(1));
+
+        let cleanup = test_transaction();
+        Key::predefined(&cleanup, HKEY_CURRENT_USER, w!("Software"))
+            .unwrap()
+            .delete_subkey(w!(
+                "registry-classes-utils-test-keys_stay_usable_across_commit"
+            ))
+            .unwrap();
+        cleanup.commit().unwrap();
+    }
+
+    #[test]
+    fn keys_close_before_rollback() {
+        let path = scratch_path("keys_close_before_rollback");
+
+        {
+            let transaction = test_transaction();
+            let key = scratch_key(&transaction, "keys_close_before_rollback");
+            key.set_u32(w!("Value"), 1).unwrap();
+
+            // The borrow makes the key go first, so its handle is closed before the transaction
+            // is rolled back on drop.
+            drop(key);
+        }
+
+        let error = read::ReadKey::open(HKEY_CURRENT_USER, PCWSTR::from_raw(path.as_ptr()))
+            .err()
+            .unwrap();
+        assert_eq!(WIN32_ERROR::from_error(&error), Some(ERROR_FILE_NOT_FOUND));
+    }
+
+    #[test]
+    fn keys_can_be_dropped_after_explicit_rollback() {
+        let path = scratch_path("keys_can_be_dropped_after_explicit_rollback");
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "keys_can_be_dropped_after_explicit_rollback");
+
+        key.set_u32(w!("Value"), 1).unwrap();
+        transaction.rollback().unwrap();
+        drop(key);
+
+        assert!(read::ReadKey::open(HKEY_CURRENT_USER, PCWSTR::from_raw(path.as_ptr())).is_err());
+    }
+
+    #[test]
+    fn threading_models_round_trip() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "threading_models_round_trip");
+
+        for model in [
+            ThreadingModel::Apartment,
+            ThreadingModel::Free,
+            ThreadingModel::Both,
+            ThreadingModel::Neutral,
+            ThreadingModel::Other("Custom".to_owned()),
+            ThreadingModel::Single,
+        ] {
+            write_threading_model(&key, &model).unwrap();
+            assert_eq!(read_threading_model(&key).unwrap(), model);
+        }
+
+        assert_eq!(
+            key.open_subkey_readonly(w!("InprocServer32"))
+                .unwrap()
+                .get_str(w!("ThreadingModel"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn threading_model_reads_missing_and_empty_as_single() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "threading_model_missing_and_empty");
+        let inproc = key.create_subkey(w!("InprocServer32")).unwrap();
+
+        assert_eq!(read_threading_model(&key).unwrap(), ThreadingModel::Single);
+
+        inproc.set_str(w!("ThreadingModel"), "").unwrap();
+        assert_eq!(read_threading_model(&key).unwrap(), ThreadingModel::Single);
+    }
+
+    #[test]
+    fn threading_model_parses_case_insensitively() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "threading_model_case_insensitive");
+        let inproc = key.create_subkey(w!("InprocServer32")).unwrap();
+
+        for (value, model) in [
+            ("apartment", ThreadingModel::Apartment),
+            ("FREE", ThreadingModel::Free),
+            ("bOTH", ThreadingModel::Both),
+            ("neutral", ThreadingModel::Neutral),
+            ("both ", ThreadingModel::Other("both ".to_owned())),
+        ] {
+            inproc.set_str(w!("ThreadingModel"), value).unwrap();
+            assert_eq!(read_threading_model(&key).unwrap(), model);
+        }
+    }
+
+    #[test]
+    fn delete_subkey_needs_only_read_and_delete_access() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "delete_subkey_read_delete");
+        let protected = key.create_subkey(w!("Protected")).unwrap();
+
+        protected.set_u32(w!("Value"), 1).unwrap();
+        protected.create_subkey(w!("Child")).unwrap();
+        with_dacl(KEY_READ.0 | DELETE.0, |descriptor| {
+            protected
+                .set_security(descriptor, DACL_SECURITY_INFORMATION)
+                .unwrap()
+        });
+        drop(protected);
+
+        key.delete_subkey(w!("Protected")).unwrap();
+        assert!(!key.subkey_exists(w!("Protected")).unwrap());
+    }
+
+    #[test]
+    fn delete_tree_reopens_key_with_delete_access() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "delete_tree_read_delete");
+        let protected = key.create_subkey(w!("Protected")).unwrap();
+
+        protected.set_u32(w!("Value"), 1).unwrap();
+        protected.create_subkey(w!("Child")).unwrap();
+        // Without KEY_CREATE_SUB_KEY the key can no longer be opened with KEY_WRITE.
+        with_dacl(KEY_READ.0 | KEY_SET_VALUE.0 | DELETE.0, |descriptor| {
+            protected
+                .set_security(descriptor, DACL_SECURITY_INFORMATION)
+                .unwrap()
+        });
+
+        protected.delete_tree().unwrap();
+        assert!(protected.subkeys().unwrap().is_empty());
+        assert!(protected.values().unwrap().is_empty());
+    }
+
+    struct CategorizedClass;
+
+    impl CoClass for CategorizedClass {
+        const CLSID: GUID = GUID::from_u128(0x6a1b2c3d_4e5f_4a6b_8c7d_9e0f1a2b3c4d);
+        const PROG_ID: PCWSTR = PCWSTR::null();
+        const VERSION_INDEPENDENT_PROG_ID: PCWSTR = w!("RegistryClassesUtils.Categorized");
+        const IMPLEMENTED_CATEGORIES: &'static [GUID] = &[
+            // CATID_SafeForScripting
+            GUID::from_u128(0x7dd95801_9882_11cf_9fa9_00aa006c42c4),
+            // CATID_SafeForInitializing
+            GUID::from_u128(0x7dd95802_9882_11cf_9fa9_00aa006c42c4),
+        ];
+    }
+
+    #[test]
+    fn implemented_categories_are_registered_by_catid() {
+        let module_path = raw::to_wide("C:\\test.dll");
+        let transaction = test_transaction();
+        let classes = scratch_key(&transaction, "implemented_categories");
+        let clsid = CategorizedClass::CLSID.to_wide();
+        let categories = raw::to_wide(&format!(
+            "CLSID\\{}\\Implemented Categories",
+            crate::com::GuidDisplay(&CategorizedClass::CLSID)
+        ));
+
+        register_com_extension::<CategorizedClass>(
+            &classes,
+            ServerKind::Inproc {
+                module_path: NullTerminatedSlice::new(&module_path).unwrap(),
+                apartment_type: ApartmentType::Both,
+            },
+            w!("Categorized class"),
+            PCWSTR::null(),
+        )
+        .unwrap();
+
+        let mut names = classes
+            .open_subkey(PCWSTR::from_raw(categories.as_ptr()))
+            .unwrap()
+            .subkeys()
+            .unwrap();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "{7dd95801-9882-11cf-9fa9-00aa006c42c4}",
+                "{7dd95802-9882-11cf-9fa9-00aa006c42c4}",
+            ]
+        );
+
+        unregister_com_extension::<CategorizedClass>(&classes).unwrap();
+
+        assert!(
+            !classes
+                .open_subkey(w!("CLSID"))
+                .unwrap()
+                .subkey_exists(PCWSTR::from_raw(clsid.as_ptr()))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn null_terminated_slice_requires_a_single_trailing_nul() {
+        let empty: &[u16] = &[];
+        let nul: &[u16] = &[0];
+        let terminated: &[u16] = &[0x61, 0x62, 0];
+
+        assert!(NullTerminatedSlice::new(empty).is_none());
+        assert_eq!(NullTerminatedSlice::new(nul).as_deref(), Some(nul));
+        assert_eq!(
+            NullTerminatedSlice::new(terminated).as_deref(),
+            Some(terminated)
+        );
+        assert!(NullTerminatedSlice::new(&[0x61, 0, 0x62, 0]).is_none());
+        assert!(NullTerminatedSlice::new(&[0x61, 0, 0]).is_none());
+        assert!(NullTerminatedSlice::new(&[0x61, 0x62]).is_none());
+    }
+
+    #[test]
+    fn null_terminated_wide_string_appends_nul() {
+        assert_eq!(*NullTerminatedWideString::new("").unwrap().as_slice(), [0]);
+        assert_eq!(
+            *NullTerminatedWideString::new("ab").unwrap().as_slice(),
+            [0x61, 0x62, 0]
+        );
+        assert_eq!(
+            *NullTerminatedWideString::new("\u{1f600}")
+                .unwrap()
+                .as_slice(),
+            [0xd83d, 0xde00, 0]
+        );
+        assert_eq!(NullTerminatedWideString::new("a\0b"), None);
+        assert_eq!(NullTerminatedWideString::new("ab\0"), None);
+    }
+
+    struct UnnamedClass;
+
+    impl CoClass for UnnamedClass {
+        const CLSID: GUID = GUID::from_u128(0x0b1c2d3e_4f50_4617_8283_949596979899);
+        const PROG_ID: PCWSTR = PCWSTR::null();
+        const VERSION_INDEPENDENT_PROG_ID: PCWSTR = PCWSTR::null();
+    }
+
+    #[test]
+    fn null_version_independent_prog_id_is_rejected() {
+        let module_path = raw::to_wide("C:\\test.dll");
+        let transaction = Transaction::dry_run(false);
+        let classes = Key::classes_root(&transaction, RegistrationScope::PerMachine).unwrap();
+
+        assert_eq!(
+            validate_prog_id(PCWSTR::null()).unwrap_err().code(),
+            E_INVALIDARG
+        );
+
+        let error = register_com_extension::<UnnamedClass>(
+            &classes,
+            ServerKind::Inproc {
+                module_path: NullTerminatedSlice::new(&module_path).unwrap(),
+                apartment_type: ApartmentType::Both,
+            },
+            w!("Unnamed class"),
+            PCWSTR::null(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(error.code(), E_INVALIDARG);
+    }
+
+    #[test]
+    fn threading_model_is_written_as_given() {
+        let module_path = raw::to_wide("C:\\test.dll");
+        let module_path = NullTerminatedSlice::new(&module_path).unwrap();
+        let transaction = test_transaction();
+        let classes = scratch_key(&transaction, "threading_model_as_given");
+
+        for (threading_model, expected) in [
+            (PCWSTR::null(), ThreadingModel::Single),
+            (w!("Custom"), ThreadingModel::Other("Custom".to_owned())),
+            (
+                ApartmentType::Neutral.as_pcwstr(),
+                ApartmentType::Neutral.into(),
+            ),
+        ] {
+            let com_object = register_com_extension_with_threading_model::<TestClass>(
+                &classes,
+                module_path,
+                w!("Test class"),
+                PCWSTR::null(),
+                threading_model,
+            )
+            .unwrap();
+
+            assert_eq!(read_threading_model(&com_object).unwrap(), expected);
+            unregister_com_extension::<TestClass>(&classes).unwrap();
+        }
+    }
+
+    #[test]
+    fn unregister_manifest_removes_legacy_prog_ids() {
+        let transaction = test_transaction();
+        let classes = scratch_key(&transaction, "unregister_legacy_prog_ids");
+        let entry = |prog_id: &str| UninstallEntry {
+            clsid: TestClass::CLSID,
+            prog_ids: vec![prog_id.to_owned()],
+        };
+
+        classes
+            .create_subkey(w!("Legacy ProgId"))
+            .unwrap()
+            .create_subkey(w!("Child"))
+            .unwrap();
+
+        for prog_id in ["", "Legacy ProgId\\Child"] {
+            let error = unregister_manifest(&classes, &[entry(prog_id)]).unwrap_err();
+
+            assert_eq!(error.code(), E_INVALIDARG);
+            assert!(classes.subkey_exists(w!("Legacy ProgId\\Child")).unwrap());
+        }
+
+        unregister_manifest(&classes, &[entry("Legacy ProgId")]).unwrap();
+        assert!(!classes.subkey_exists(w!("Legacy ProgId")).unwrap());
+    }
+
+    #[test]
+    fn strings_are_written_nul_terminated() {
+        let transaction = test_transaction();
+        let key = scratch_key(&transaction, "strings_nul_terminated");
+
+        key.set_str(w!("Str"), "ab").unwrap();
+        key.set_str_expand(w!("Expand"), "ab").unwrap();
+        key.set_pcwstr(w!("Pcwstr"), w!("ab")).unwrap();
+        key.set_pcwstr(w!("Null"), PCWSTR::null()).unwrap();
+        key.set_default_str("ab").unwrap();
+
+        for name in [w!("Str"), w!("Expand"), w!("Pcwstr"), PCWSTR::null()] {
+            let (_, data) = key.get_raw(name).unwrap().unwrap();
+            assert_eq!(data, [0x61, 0, 0x62, 0, 0, 0]);
+        }
+
+        assert_eq!(
+            key.get_raw(w!("Null")).unwrap().unwrap(),
+            (REG_SZ, vec![0, 0])
+        );
+    }
+
+    #[test]
+    fn key_cache_rejects_null_subkey() {
+        let transaction = test_transaction();
+        let classes = scratch_key(&transaction, "key_cache_null_subkey");
+        let cache = KeyCache::new(&classes);
+
+        let error = cache.create_subkey(PCWSTR::null()).err().unwrap();
+        assert_eq!(error.code(), E_INVALIDARG);
+    }
+
+    #[test]
+    fn preview_handlers_are_listed_below_the_given_software_key() {
+        let transaction = test_transaction();
+        let root = scratch_key(&transaction, "preview_handler");
+        let classes = root.create_subkey(w!("Classes")).unwrap();
+        let software = root.create_subkey(w!("Software")).unwrap();
+
+        register_preview_handler(
+            &classes,
+            &software,
+            w!("RegistryClassesUtils.Test"),
+            &TestClass::CLSID,
+            w!("Test Preview Handler"),
+        )
+        .unwrap();
+
+        let category = guid_path(
+            "RegistryClassesUtils.Test\\ShellEx",
+            &PREVIEW_HANDLER_CATEGORY,
+        );
+        assert!(
+            classes
+                .subkey_exists(PCWSTR::from_raw(category.as_ptr()))
+                .unwrap()
+        );
+
+        let clsid = TestClass::CLSID.to_wide();
+        let handlers = software
+            .try_open_subkey(w!("Microsoft\\Windows\\CurrentVersion\\PreviewHandlers"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            handlers
+                .get_str(PCWSTR::from_raw(clsid.as_ptr()))
+                .unwrap()
+                .as_deref(),
+            Some("Test Preview Handler")
+        );
+    }
+}
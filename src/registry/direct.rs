@@ -0,0 +1,337 @@
+use std::marker::PhantomData;
+
+use windows::{
+    Win32::{
+        Foundation::{
+            ERROR_ALREADY_EXISTS, ERROR_CALL_NOT_IMPLEMENTED, ERROR_FILE_NOT_FOUND,
+            ERROR_INVALID_FUNCTION, ERROR_NOT_SUPPORTED, ERROR_SUCCESS, WIN32_ERROR,
+        },
+        System::{
+            LibraryLoader::{GetModuleHandleW, GetProcAddress},
+            Registry::{
+                HKEY, KEY_READ, KEY_WRITE, REG_OPTION_NON_VOLATILE, REG_VALUE_TYPE,
+                RegCreateKeyExW, RegOpenKeyExW,
+            },
+        },
+    },
+    core::{Owned, PCWSTR, s, w},
+};
+
+use super::{
+    raw::{check_depth, delete_tree, delete_value, set_value, to_wide},
+    read::RegistryRead,
+    write::RegistryWrite,
+};
+
+/// A non-transacted key for registries KTM does not cover, such as remote machines and loaded
+/// hives. Every write takes effect immediately. The lifetime ties the key to whatever keeps its
+/// registry reachable.
+pub struct DirectKey<'a> {
+    key: Owned<HKEY>,
+    _registry: PhantomData<&'a ()>,
+}
+
+impl DirectKey<'_> {
+    pub(crate) fn new(key: Owned<HKEY>) -> Self {
+        Self {
+            key,
+            _registry: PhantomData,
+        }
+    }
+
+    pub(crate) fn open(key: HKEY, sub_key: PCWSTR) -> windows::core::Result<Self> {
+        let mut result = HKEY::default();
+
+        unsafe {
+            RegOpenKeyExW(key, sub_key, None, KEY_READ | KEY_WRITE, &raw mut result).ok()?;
+            Ok(Self::new(Owned::new(result)))
+        }
+    }
+}
+
+impl DirectKey<'_> {
+    pub fn open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Self> {
+        Self::open(*self.key, sub_key)
+    }
+
+    /// Renames the subkey `old` to `new` in place with `RegRenameKey`, which keeps the key's
+    /// security and class. Fails with `ERROR_ALREADY_EXISTS` if `new` exists.
+    ///
+    /// `RegRenameKey` is looked up at runtime, and where advapi32 does not export it or the
+    /// registry rejects it as unsupported, such as on some remote machines, this falls back to
+    /// copying the subtree and deleting the original, like
+    /// [`Key::rename_subkey`](super::transaction::Key::rename_subkey) always does. The copy gets
+    /// default security, and unlike the transacted version a failure part way through can leave
+    /// both keys behind.
+    pub fn rename_subkey(&self, old: PCWSTR, new: PCWSTR) -> windows::core::Result<()> {
+        if self.subkey_exists(new)? {
+            return Err(ERROR_ALREADY_EXISTS.into());
+        }
+
+        let Some(rename_key) = reg_rename_key() else {
+            return self.rename_by_copy(old, new);
+        };
+
+        match unsafe { rename_key(*self.key, old, new) } {
+            ERROR_SUCCESS => Ok(()),
+            ERROR_CALL_NOT_IMPLEMENTED | ERROR_INVALID_FUNCTION | ERROR_NOT_SUPPORTED => {
+                self.rename_by_copy(old, new)
+            }
+            e => e.ok(),
+        }
+    }
+
+    fn rename_by_copy(&self, old: PCWSTR, new: PCWSTR) -> windows::core::Result<()> {
+        copy_tree(&self.open_subkey(old)?, &self.create_subkey(new)?, 0)?;
+        self.delete_subkey(old)
+    }
+}
+
+impl RegistryWrite for DirectKey<'_> {
+    fn create_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Self> {
+        let mut result = HKEY::default();
+
+        unsafe {
+            RegCreateKeyExW(
+                *self.key,
+                sub_key,
+                None,
+                None,
+                REG_OPTION_NON_VOLATILE,
+                KEY_READ | KEY_WRITE,
+                None,
+                &raw mut result,
+                None,
+            )
+            .ok()?;
+            Ok(Self::new(Owned::new(result)))
+        }
+    }
+
+    fn delete_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<()> {
+        unsafe { delete_tree(*self.key, sub_key) }
+    }
+
+    fn set_raw(
+        &self,
+        name: PCWSTR,
+        value_type: REG_VALUE_TYPE,
+        data: &[u8],
+    ) -> windows::core::Result<()> {
+        unsafe { set_value(*self.key, name, value_type, data) }
+    }
+
+    fn delete_value(&self, name: PCWSTR) -> windows::core::Result<()> {
+        unsafe { delete_value(*self.key, name) }
+    }
+}
+
+type RegRenameKeyFn = unsafe extern "system" fn(HKEY, PCWSTR, PCWSTR) -> WIN32_ERROR;
+
+/// Resolves `RegRenameKey` from advapi32, which is already loaded for the other registry
+/// functions, so its absence is not a load-time failure.
+fn reg_rename_key() -> Option<RegRenameKeyFn> {
+    unsafe {
+        let advapi32 = GetModuleHandleW(w!("advapi32.dll")).ok()?;
+        let rename_key = GetProcAddress(advapi32, s!("RegRenameKey"))?;
+
+        Some(std::mem::transmute::<
+            unsafe extern "system" fn() -> isize,
+            RegRenameKeyFn,
+        >(rename_key))
+    }
+}
+
+/// Copies the values and subkeys of `source` into `dest`, keeping value types.
+fn copy_tree(source: &DirectKey, dest: &DirectKey, depth: usize) -> windows::core::Result<()> {
+    check_depth(depth)?;
+
+    for name in source.values()? {
+        let name = to_wide(&name);
+        let name = PCWSTR::from_raw(name.as_ptr());
+
+        if let Some((value_type, data)) = source.get_raw(name)? {
+            dest.set_raw(name, value_type, &data)?;
+        }
+    }
+
+    for name in source.subkeys()? {
+        let name = to_wide(&name);
+        let name = PCWSTR::from_raw(name.as_ptr());
+
+        if let Some(subkey) = source.try_open_subkey(name)? {
+            copy_tree(&subkey, &dest.create_subkey(name)?, depth + 1)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl RegistryRead for DirectKey<'_> {
+    fn raw_handle(&self) -> HKEY {
+        *self.key
+    }
+
+    fn try_open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Option<Self>> {
+        match self.open_subkey(sub_key) {
+            Ok(key) => Ok(Some(key)),
+            Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_FILE_NOT_FOUND) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Deref;
+
+    use windows::{Win32::System::Registry::HKEY_CURRENT_USER, core::GUID};
+
+    use super::*;
+    use crate::{
+        com::{CoClass, GuidDisplay},
+        registry::{
+            ApartmentType, ComRegistration, NullTerminatedSlice, ServerKind,
+            unregister_com_extension,
+        },
+    };
+
+    /// A scratch key directly below `HKEY_CURRENT_USER\Software`, named like the transacted
+    /// tests' keys. Writes through a `DirectKey` are not rolled back, so the key is deleted again
+    /// when this is dropped, even if the test fails.
+    struct ScratchKey {
+        key: Option<DirectKey<'static>>,
+        path: Vec<u16>,
+    }
+
+    impl ScratchKey {
+        fn new(name: &str) -> Self {
+            let path = to_wide(&format!("Software\\registry-classes-utils-test-{name}"));
+            let sub_key = PCWSTR::from_raw(path.as_ptr());
+
+            unsafe { delete_tree(HKEY_CURRENT_USER, sub_key) }.unwrap();
+            let key = DirectKey::open(HKEY_CURRENT_USER, PCWSTR::null())
+                .unwrap()
+                .create_subkey(sub_key)
+                .unwrap();
+
+            Self {
+                key: Some(key),
+                path,
+            }
+        }
+    }
+
+    impl Deref for ScratchKey {
+        type Target = DirectKey<'static>;
+
+        fn deref(&self) -> &Self::Target {
+            self.key.as_ref().unwrap()
+        }
+    }
+
+    impl Drop for ScratchKey {
+        fn drop(&mut self) {
+            // Close the handle first, so the key is deleted rather than only marked for deletion.
+            self.key = None;
+            let _ = unsafe { delete_tree(HKEY_CURRENT_USER, PCWSTR::from_raw(self.path.as_ptr())) };
+        }
+    }
+
+    fn populate(parent: &DirectKey, name: PCWSTR) {
+        let key = parent.create_subkey(name).unwrap();
+        key.set_u32(w!("Value"), 1).unwrap();
+        key.create_subkey(w!("Child"))
+            .unwrap()
+            .set_pcwstr(PCWSTR::null(), w!("child"))
+            .unwrap();
+    }
+
+    fn assert_renamed(parent: &DirectKey) {
+        assert!(!parent.subkey_exists(w!("Old")).unwrap());
+
+        let new = parent.open_subkey(w!("New")).unwrap();
+        assert_eq!(new.get_u32(w!("Value")).unwrap(), Some(1));
+        assert_eq!(
+            new.open_subkey(w!("Child"))
+                .unwrap()
+                .get_str(PCWSTR::null())
+                .unwrap()
+                .as_deref(),
+            Some("child")
+        );
+    }
+
+    #[test]
+    fn rename_subkey_renames_in_place() {
+        let parent = ScratchKey::new("direct_rename_subkey");
+
+        populate(&parent, w!("Old"));
+        parent.rename_subkey(w!("Old"), w!("New")).unwrap();
+        assert_renamed(&parent);
+    }
+
+    #[test]
+    fn rename_by_copy_moves_subtree() {
+        let parent = ScratchKey::new("direct_rename_by_copy");
+
+        populate(&parent, w!("Old"));
+        parent.rename_by_copy(w!("Old"), w!("New")).unwrap();
+        assert_renamed(&parent);
+    }
+
+    #[test]
+    fn rename_subkey_fails_if_new_exists() {
+        let parent = ScratchKey::new("direct_rename_subkey_exists");
+
+        populate(&parent, w!("Old"));
+        parent.create_subkey(w!("New")).unwrap();
+
+        let error = parent.rename_subkey(w!("Old"), w!("New")).unwrap_err();
+        assert_eq!(WIN32_ERROR::from_error(&error), Some(ERROR_ALREADY_EXISTS));
+        assert!(parent.subkey_exists(w!("Old")).unwrap());
+    }
+
+    struct DirectClass;
+
+    impl CoClass for DirectClass {
+        const CLSID: GUID = GUID::from_u128(0x5e6f7a8b_9c0d_4e1f_a2b3_c4d5e6f7a8b9);
+        const PROG_ID: PCWSTR = PCWSTR::null();
+        const VERSION_INDEPENDENT_PROG_ID: PCWSTR = w!("RegistryClassesUtils.Direct");
+    }
+
+    #[test]
+    fn registers_classes_through_direct_keys() {
+        let classes = ScratchKey::new("direct_register");
+        let module_path = to_wide("C:\\test.dll");
+
+        let com_object = ComRegistration::<DirectClass>::new(ServerKind::Inproc {
+            module_path: NullTerminatedSlice::new(&module_path).unwrap(),
+            apartment_type: ApartmentType::Both,
+        })
+        .description(w!("Direct class"))
+        .register(&*classes)
+        .unwrap();
+
+        assert_eq!(
+            com_object.get_str(PCWSTR::null()).unwrap().as_deref(),
+            Some("Direct class")
+        );
+        assert_eq!(
+            classes
+                .open_subkey(w!("RegistryClassesUtils.Direct\\CLSID"))
+                .unwrap()
+                .get_str(PCWSTR::null())
+                .unwrap(),
+            Some(GuidDisplay(&DirectClass::CLSID).to_string())
+        );
+
+        drop(com_object);
+        unregister_com_extension::<DirectClass>(&*classes).unwrap();
+        assert!(
+            !classes
+                .subkey_exists(w!("RegistryClassesUtils.Direct"))
+                .unwrap()
+        );
+    }
+}
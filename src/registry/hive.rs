@@ -0,0 +1,91 @@
+use windows::{
+    Win32::{
+        Foundation::{ERROR_PRIVILEGE_NOT_HELD, WIN32_ERROR},
+        System::Registry::{HKEY, RegLoadKeyW, RegUnLoadKeyW},
+    },
+    core::PCWSTR,
+};
+
+use super::{PredefinedKey, direct::DirectKey};
+
+/// A hive file mounted by [`load_hive`], which is unloaded again on drop.
+pub struct LoadedHive {
+    root: HKEY,
+    mount_name: Vec<u16>,
+    loaded: bool,
+}
+
+/// Mounts the hive in `file` as `mount_name` below `predefined`, for example to register classes
+/// in the `SOFTWARE` hive of an offline Windows image.
+///
+/// Loading and unloading hives requires the `SeRestorePrivilege` (`SE_RESTORE_NAME`) and
+/// `SeBackupPrivilege` (`SE_BACKUP_NAME`) privileges to be enabled in the caller's token. KTM does
+/// not cover loaded hives, so keys in them are non-transacted.
+pub fn load_hive(
+    predefined: PredefinedKey,
+    mount_name: PCWSTR,
+    file: PCWSTR,
+) -> windows::core::Result<LoadedHive> {
+    let root = predefined.into();
+
+    unsafe { RegLoadKeyW(root, mount_name, file) }
+        .ok()
+        .map_err(privilege_error)?;
+
+    Ok(LoadedHive {
+        root,
+        mount_name: unsafe { mount_name.as_wide() }
+            .iter()
+            .copied()
+            .chain(std::iter::once(0))
+            .collect(),
+        loaded: true,
+    })
+}
+
+fn privilege_error(e: windows::core::Error) -> windows::core::Error {
+    if WIN32_ERROR::from_error(&e) == Some(ERROR_PRIVILEGE_NOT_HELD) {
+        windows::core::Error::new(
+            e.code(),
+            "loading and unloading hives requires SeRestorePrivilege and SeBackupPrivilege to be \
+             enabled",
+        )
+    } else {
+        e
+    }
+}
+
+impl LoadedHive {
+    /// Opens the root of the mounted hive. Every key opened from it has to be closed before the
+    /// hive can be unloaded, which the borrow enforces.
+    ///
+    /// The key implements [`RegistryWrite`](super::write::RegistryWrite), so classes are
+    /// registered into the hive with [`ComRegistration::register`](super::ComRegistration::register)
+    /// on its `Classes` subkey.
+    pub fn root(&self) -> windows::core::Result<DirectKey<'_>> {
+        DirectKey::open(self.root, self.mount_name())
+    }
+
+    /// Unloads the hive, reporting errors that dropping it would ignore.
+    pub fn unload(mut self) -> windows::core::Result<()> {
+        self.loaded = false;
+
+        unsafe { RegUnLoadKeyW(self.root, self.mount_name()) }
+            .ok()
+            .map_err(privilege_error)
+    }
+
+    fn mount_name(&self) -> PCWSTR {
+        PCWSTR::from_raw(self.mount_name.as_ptr())
+    }
+}
+
+impl Drop for LoadedHive {
+    fn drop(&mut self) {
+        if self.loaded {
+            unsafe {
+                let _ = RegUnLoadKeyW(self.root, self.mount_name());
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use windows::{Win32::System::Registry::REG_VALUE_TYPE, core::PCWSTR};
+
+use super::{
+    raw::{check_depth, join_path, to_wide, with_nul},
+    read::RegistryRead,
+    transaction::Key,
+};
+
+/// A single registry mutation. Paths are relative to the key the operation is applied to, with
+/// the empty path naming that key itself. Value names are kept as the UTF-16 they were given in,
+/// without a nul, so names that are not valid UTF-16 survive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegOp {
+    CreateKey {
+        path: String,
+    },
+    DeleteKey {
+        path: String,
+    },
+    /// Deletes the values and subkeys of the key at `path`, but keeps the key itself along with
+    /// its security, class and volatility.
+    ClearKey {
+        path: String,
+    },
+    SetValue {
+        path: String,
+        name: Vec<u16>,
+        value_type: REG_VALUE_TYPE,
+        data: Vec<u8>,
+    },
+    DeleteValue {
+        path: String,
+        name: Vec<u16>,
+    },
+}
+
+impl RegOp {
+    /// Returns this operation with `base` prepended to its path.
+    pub(crate) fn rebased(mut self, base: &str) -> Self {
+        match &mut self {
+            Self::CreateKey { path }
+            | Self::DeleteKey { path }
+            | Self::ClearKey { path }
+            | Self::SetValue { path, .. }
+            | Self::DeleteValue { path, .. } => *path = join_path(base, path),
+        }
+
+        self
+    }
+
+    pub fn apply(&self, root: &Key) -> windows::core::Result<()> {
+        match self {
+            Self::CreateKey { path } => {
+                root.create_subkey(PCWSTR::from_raw(to_wide(path).as_ptr()))?;
+                Ok(())
+            }
+            Self::DeleteKey { path } => {
+                root.delete_subkey(PCWSTR::from_raw(to_wide(path).as_ptr()))
+            }
+            Self::ClearKey { path } if path.is_empty() => root.delete_tree(),
+            Self::ClearKey { path } => {
+                match root.try_open_subkey(PCWSTR::from_raw(to_wide(path).as_ptr()))? {
+                    Some(key) => key.delete_tree(),
+                    None => Ok(()),
+                }
+            }
+            Self::SetValue {
+                path,
+                name,
+                value_type,
+                data,
+            } => root
+                .create_subkey(PCWSTR::from_raw(to_wide(path).as_ptr()))?
+                .set_raw(PCWSTR::from_raw(with_nul(name).as_ptr()), *value_type, data),
+            Self::DeleteValue { path, name } => root.delete_value_at(
+                PCWSTR::from_raw(to_wide(path).as_ptr()),
+                PCWSTR::from_raw(with_nul(name).as_ptr()),
+            ),
+        }
+    }
+}
+
+pub fn apply_ops(root: &Key, ops: &[RegOp]) -> windows::core::Result<()> {
+    ops.iter().try_for_each(|op| op.apply(root))
+}
+
+fn open<R: RegistryRead>(before: &R, path: &str) -> windows::core::Result<Option<R>> {
+    before.try_open_subkey(PCWSTR::from_raw(to_wide(path).as_ptr()))
+}
+
+/// Returns the outermost component of `path` that does not exist yet in `before`.
+fn topmost_missing(
+    before: &impl RegistryRead,
+    path: &str,
+) -> windows::core::Result<Option<String>> {
+    let mut prefix = String::new();
+
+    for component in path.split('\\').filter(|c| !c.is_empty()) {
+        prefix = join_path(&prefix, component);
+
+        if open(before, &prefix)?.is_none() {
+            return Ok(Some(prefix));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Appends the operations that recreate `key` at `path`, values and subkeys included. Fails
+/// instead of recursing forever if a symbolic link loops back into the tree.
+pub(crate) fn snapshot<R: RegistryRead>(
+    key: &R,
+    path: &str,
+    ops: &mut Vec<RegOp>,
+) -> windows::core::Result<()> {
+    snapshot_at(key, path, 0, ops)
+}
+
+fn snapshot_at<R: RegistryRead>(
+    key: &R,
+    path: &str,
+    depth: usize,
+    ops: &mut Vec<RegOp>,
+) -> windows::core::Result<()> {
+    check_depth(depth)?;
+
+    ops.push(RegOp::CreateKey {
+        path: path.to_owned(),
+    });
+
+    for name in key.values()? {
+        let value = key.get_raw(PCWSTR::from_raw(to_wide(&name).as_ptr()))?;
+
+        if let Some((value_type, data)) = value {
+            ops.push(RegOp::SetValue {
+                path: path.to_owned(),
+                name: name.encode_utf16().collect(),
+                value_type,
+                data,
+            });
+        }
+    }
+
+    for name in key.subkeys()? {
+        if let Some(subkey) = key.try_open_subkey(PCWSTR::from_raw(to_wide(&name).as_ptr()))? {
+            snapshot_at(&subkey, &join_path(path, &name), depth + 1, ops)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Computes the operations that revert `ops` when applied after them, given the state `before`
+/// they ran, which may be a committed [`ReadKey`](super::read::ReadKey) or a transacted
+/// [`Key`]. Values that existed are restored with their original type and data, and keys that
+/// did not exist are deleted again.
+pub fn inverse_ops<R: RegistryRead>(
+    ops: &[RegOp],
+    before: &R,
+) -> windows::core::Result<Vec<RegOp>> {
+    let mut inverse = Vec::new();
+
+    for op in ops.iter().rev() {
+        match op {
+            RegOp::CreateKey { path } => {
+                if let Some(path) = topmost_missing(before, path)? {
+                    inverse.push(RegOp::DeleteKey { path });
+                }
+            }
+            RegOp::DeleteKey { path } | RegOp::ClearKey { path } => {
+                if let Some(key) = open(before, path)? {
+                    snapshot(&key, path, &mut inverse)?;
+                }
+            }
+            RegOp::SetValue { path, name, .. } | RegOp::DeleteValue { path, name } => {
+                let is_set = matches!(op, RegOp::SetValue { .. });
+
+                let previous = match open(before, path)? {
+                    Some(key) => key.get_raw(PCWSTR::from_raw(with_nul(name).as_ptr()))?,
+                    None => {
+                        if is_set && let Some(path) = topmost_missing(before, path)? {
+                            inverse.push(RegOp::DeleteKey { path });
+                        }
+
+                        continue;
+                    }
+                };
+
+                match previous {
+                    Some((value_type, data)) => inverse.push(RegOp::SetValue {
+                        path: path.clone(),
+                        name: name.clone(),
+                        value_type,
+                        data,
+                    }),
+                    None if is_set => inverse.push(RegOp::DeleteValue {
+                        path: path.clone(),
+                        name: name.clone(),
+                    }),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    Ok(inverse)
+}
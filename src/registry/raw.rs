@@ -0,0 +1,257 @@
+use windows::{
+    Win32::{
+        Foundation::{
+            ERROR_CANT_RESOLVE_FILENAME, ERROR_FILE_NOT_FOUND, ERROR_MORE_DATA,
+            ERROR_NO_MORE_ITEMS, ERROR_SUCCESS, FILETIME, WIN32_ERROR,
+        },
+        System::{
+            Environment::ExpandEnvironmentStringsW,
+            Registry::{
+                HKEY, REG_VALUE_TYPE, RegDeleteTreeW, RegDeleteValueW, RegEnumKeyExW,
+                RegEnumValueW, RegQueryInfoKeyW, RegQueryValueExW, RegSetValueExW,
+            },
+        },
+    },
+    core::{PCWSTR, PWSTR},
+};
+
+pub(crate) unsafe fn query_value(
+    key: HKEY,
+    name: PCWSTR,
+) -> windows::core::Result<Option<(REG_VALUE_TYPE, Vec<u8>)>> {
+    let mut data = Vec::new();
+
+    loop {
+        let mut value_type = REG_VALUE_TYPE::default();
+        let mut size = data.len() as u32;
+
+        match unsafe {
+            RegQueryValueExW(
+                key,
+                name,
+                None,
+                Some(&raw mut value_type),
+                if data.is_empty() {
+                    None
+                } else {
+                    Some(data.as_mut_ptr())
+                },
+                Some(&raw mut size),
+            )
+        } {
+            ERROR_FILE_NOT_FOUND => return Ok(None),
+            ERROR_SUCCESS if data.is_empty() && size != 0 => data.resize(size as usize, 0),
+            ERROR_SUCCESS => {
+                data.truncate(size as usize);
+                return Ok(Some((value_type, data)));
+            }
+            ERROR_MORE_DATA => data.resize(size as usize, 0),
+            e => return Err(e.into()),
+        }
+    }
+}
+
+pub(crate) unsafe fn value_exists(key: HKEY, name: PCWSTR) -> windows::core::Result<bool> {
+    match unsafe { RegQueryValueExW(key, name, None, None, None, None) } {
+        ERROR_SUCCESS => Ok(true),
+        ERROR_FILE_NOT_FOUND => Ok(false),
+        e => Err(e.into()),
+    }
+}
+
+pub(crate) unsafe fn set_value(
+    key: HKEY,
+    name: PCWSTR,
+    value_type: REG_VALUE_TYPE,
+    data: &[u8],
+) -> windows::core::Result<()> {
+    unsafe { RegSetValueExW(key, name, None, value_type, Some(data)).ok() }
+}
+
+/// Deletes a value, treating a missing value as success.
+pub(crate) unsafe fn delete_value(key: HKEY, name: PCWSTR) -> windows::core::Result<()> {
+    match unsafe { RegDeleteValueW(key, name) } {
+        ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => Ok(()),
+        e => e.ok(),
+    }
+}
+
+/// Deletes a key and its subtree, treating a missing key as success.
+pub(crate) unsafe fn delete_tree(key: HKEY, sub_key: PCWSTR) -> windows::core::Result<()> {
+    match unsafe { RegDeleteTreeW(key, sub_key) } {
+        ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => Ok(()),
+        e => e.ok(),
+    }
+}
+
+/// Reinterprets string value data as UTF-16, up to the first nul.
+pub(crate) fn decode_wide(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect()
+}
+
+pub(crate) fn decode_str(data: &[u8]) -> windows::core::Result<String> {
+    Ok(String::from_utf16(&decode_wide(data))?)
+}
+
+pub(crate) fn expand_environment_strings(value: &str) -> windows::core::Result<String> {
+    let source = to_wide(value);
+    let mut buffer = vec![0u16; source.len()];
+
+    loop {
+        let size = unsafe {
+            ExpandEnvironmentStringsW(PCWSTR::from_raw(source.as_ptr()), Some(&mut buffer))
+        } as usize;
+
+        if size == 0 {
+            return Err(windows::core::Error::from_win32());
+        } else if size <= buffer.len() {
+            buffer.truncate(size - 1);
+            return Ok(String::from_utf16(&buffer)?);
+        } else {
+            buffer.resize(size, 0);
+        }
+    }
+}
+
+pub(crate) fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// How deep a registry tree can nest. Walking any deeper means a symbolic link loops back into
+/// the tree being walked.
+const MAX_KEY_DEPTH: usize = 512;
+
+/// Fails once a recursive walk goes deeper than any real registry tree.
+pub(crate) fn check_depth(depth: usize) -> windows::core::Result<()> {
+    if depth > MAX_KEY_DEPTH {
+        return Err(windows::core::Error::new(
+            ERROR_CANT_RESOLVE_FILENAME.to_hresult(),
+            format!("key tree is more than {MAX_KEY_DEPTH} levels deep, a symbolic link loops"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Appends the nul that a `PCWSTR` needs to a wide string stored without one.
+pub(crate) fn with_nul(value: &[u16]) -> Vec<u16> {
+    value.iter().copied().chain(std::iter::once(0)).collect()
+}
+
+/// Returns `value` including its terminating nul, which `REG_SZ` data has to end in, or just a
+/// nul if `value` is null.
+///
+/// # Safety
+///
+/// `value` must be null or point to a nul-terminated string.
+pub(crate) unsafe fn pcwstr_with_nul(value: &PCWSTR) -> &[u16] {
+    if value.is_null() {
+        &[0]
+    } else {
+        unsafe { std::slice::from_raw_parts(value.as_ptr(), value.len() + 1) }
+    }
+}
+
+pub(crate) fn join_path(parent: &str, child: &str) -> String {
+    if parent.is_empty() {
+        child.to_owned()
+    } else if child.is_empty() {
+        parent.to_owned()
+    } else {
+        format!("{parent}\\{child}")
+    }
+}
+
+fn enum_names(
+    max_length: u32,
+    enumerate: impl Fn(u32, PWSTR, *mut u32) -> WIN32_ERROR,
+) -> windows::core::Result<Vec<String>> {
+    let mut buffer = vec![0u16; max_length as usize + 1];
+    let mut names = Vec::new();
+    let mut index = 0;
+
+    loop {
+        let mut length = buffer.len() as u32;
+
+        match enumerate(index, PWSTR::from_raw(buffer.as_mut_ptr()), &raw mut length) {
+            ERROR_SUCCESS => {
+                names.push(String::from_utf16(&buffer[..length as usize])?);
+                index += 1;
+            }
+            // The key changed after RegQueryInfoKeyW; retry this index with more room.
+            ERROR_MORE_DATA => buffer.resize(buffer.len() * 2, 0),
+            ERROR_NO_MORE_ITEMS => break,
+            e => return Err(e.into()),
+        }
+    }
+
+    Ok(names)
+}
+
+unsafe fn query_max_name_lengths(key: HKEY) -> windows::core::Result<(u32, u32)> {
+    let mut max_subkey_len = 0u32;
+    let mut max_value_name_len = 0u32;
+
+    unsafe {
+        RegQueryInfoKeyW(
+            key,
+            None,
+            None,
+            None,
+            None,
+            Some(&raw mut max_subkey_len),
+            None,
+            None,
+            Some(&raw mut max_value_name_len),
+            None,
+            None,
+            None,
+        )
+        .ok()?;
+    }
+
+    Ok((max_subkey_len, max_value_name_len))
+}
+
+pub(crate) unsafe fn query_last_write_time(key: HKEY) -> windows::core::Result<FILETIME> {
+    let mut last_write_time = FILETIME::default();
+
+    unsafe {
+        RegQueryInfoKeyW(
+            key,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(&raw mut last_write_time),
+        )
+        .ok()?;
+    }
+
+    Ok(last_write_time)
+}
+
+pub(crate) unsafe fn enum_subkeys(key: HKEY) -> windows::core::Result<Vec<String>> {
+    let (max_length, _) = unsafe { query_max_name_lengths(key)? };
+
+    enum_names(max_length, |index, name, length| unsafe {
+        RegEnumKeyExW(key, index, Some(name), length, None, None, None, None)
+    })
+}
+
+pub(crate) unsafe fn enum_values(key: HKEY) -> windows::core::Result<Vec<String>> {
+    let (_, max_length) = unsafe { query_max_name_lengths(key)? };
+
+    enum_names(max_length, |index, name, length| unsafe {
+        RegEnumValueW(key, index, Some(name), length, None, None, None, None)
+    })
+}
@@ -0,0 +1,361 @@
+use std::{
+    ffi::OsString,
+    os::windows::ffi::OsStringExt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use windows::{
+    Win32::{
+        Foundation::{
+            ERROR_DATATYPE_MISMATCH, ERROR_FILE_NOT_FOUND, ERROR_INVALID_DATA, FILETIME,
+            WIN32_ERROR,
+        },
+        System::Registry::{
+            HKEY, KEY_READ, REG_BINARY, REG_DWORD, REG_EXPAND_SZ, REG_NONE, REG_QWORD, REG_SZ,
+            REG_VALUE_TYPE, RegOpenKeyExW,
+        },
+    },
+    core::{Owned, PCWSTR},
+};
+
+use super::raw::{
+    check_depth, decode_str, decode_wide, enum_subkeys, enum_values, expand_environment_strings,
+    query_last_write_time, query_value, to_wide, value_exists,
+};
+
+/// A read-only, non-transacted registry key that sees the committed state.
+pub struct ReadKey {
+    key: Owned<HKEY>,
+}
+
+impl ReadKey {
+    pub fn open(key: HKEY, sub_key: PCWSTR) -> windows::core::Result<Self> {
+        let mut result = HKEY::default();
+
+        unsafe {
+            RegOpenKeyExW(key, sub_key, None, KEY_READ, &raw mut result).ok()?;
+            Ok(Self {
+                key: Owned::new(result),
+            })
+        }
+    }
+
+    pub fn open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Self> {
+        Self::open(*self.key, sub_key)
+    }
+}
+
+impl RegistryRead for ReadKey {
+    fn raw_handle(&self) -> HKEY {
+        *self.key
+    }
+
+    fn try_open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Option<Self>> {
+        match self.open_subkey(sub_key) {
+            Ok(key) => Ok(Some(key)),
+            Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_FILE_NOT_FOUND) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Read accessors shared by transacted [`Key`](super::transaction::Key)s and non-transacted
+/// [`ReadKey`]s, so verification code can run against either a pending transaction or the
+/// committed state.
+pub trait RegistryRead {
+    fn raw_handle(&self) -> HKEY;
+
+    /// Opens `sub_key` in the same view as this key, returning `None` if it does not exist.
+    fn try_open_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Option<Self>>
+    where
+        Self: Sized;
+
+    fn value_exists(&self, name: PCWSTR) -> windows::core::Result<bool> {
+        unsafe { value_exists(self.raw_handle(), name) }
+    }
+
+    fn subkey_exists(&self, sub_key: PCWSTR) -> windows::core::Result<bool>
+    where
+        Self: Sized,
+    {
+        Ok(self.try_open_subkey(sub_key)?.is_some())
+    }
+
+    fn get_raw(&self, name: PCWSTR) -> windows::core::Result<Option<(REG_VALUE_TYPE, Vec<u8>)>> {
+        unsafe { query_value(self.raw_handle(), name) }
+    }
+
+    fn get_u32(&self, name: PCWSTR) -> windows::core::Result<Option<u32>> {
+        match self.get_raw(name)? {
+            Some((REG_DWORD, data)) => {
+                Ok(Some(u32::from_le_bytes(data.try_into().map_err(|_| {
+                    windows::core::Error::from(ERROR_INVALID_DATA)
+                })?)))
+            }
+            Some(_) => Err(ERROR_DATATYPE_MISMATCH.into()),
+            None => Ok(None),
+        }
+    }
+
+    fn get_u64(&self, name: PCWSTR) -> windows::core::Result<Option<u64>> {
+        match self.get_raw(name)? {
+            Some((REG_QWORD, data)) => {
+                Ok(Some(u64::from_le_bytes(data.try_into().map_err(|_| {
+                    windows::core::Error::from(ERROR_INVALID_DATA)
+                })?)))
+            }
+            Some(_) => Err(ERROR_DATATYPE_MISMATCH.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a `REG_DWORD` written by [`Key::set_i32`](super::transaction::Key::set_i32).
+    fn get_i32(&self, name: PCWSTR) -> windows::core::Result<Option<i32>> {
+        Ok(self.get_u32(name)?.map(|value| value as i32))
+    }
+
+    /// Reads a `REG_QWORD` written by [`Key::set_i64`](super::transaction::Key::set_i64).
+    fn get_i64(&self, name: PCWSTR) -> windows::core::Result<Option<i64>> {
+        Ok(self.get_u64(name)?.map(|value| value as i64))
+    }
+
+    /// Returns whether a `REG_NONE` value named `name` exists.
+    fn has_none_value(&self, name: PCWSTR) -> windows::core::Result<bool> {
+        Ok(matches!(self.get_raw(name)?, Some((REG_NONE, _))))
+    }
+
+    fn get_binary(&self, name: PCWSTR) -> windows::core::Result<Option<Vec<u8>>> {
+        match self.get_raw(name)? {
+            Some((REG_BINARY, data)) => Ok(Some(data)),
+            Some(_) => Err(ERROR_DATATYPE_MISMATCH.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a bitmask written by [`Key::set_flags`](super::transaction::Key::set_flags).
+    fn get_flags<F: From<u32>>(&self, name: PCWSTR) -> windows::core::Result<Option<F>> {
+        Ok(self.get_u32(name)?.map(F::from))
+    }
+
+    fn get_str(&self, name: PCWSTR) -> windows::core::Result<Option<String>> {
+        match self.get_raw(name)? {
+            Some((REG_SZ | REG_EXPAND_SZ, data)) => Ok(Some(decode_str(&data)?)),
+            Some(_) => Err(ERROR_DATATYPE_MISMATCH.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a string value, expanding environment variables if it is a `REG_EXPAND_SZ` and
+    /// `expand` is set. [`RegistryRead::get_raw`] tells the two types apart.
+    fn get_expand_str(&self, name: PCWSTR, expand: bool) -> windows::core::Result<Option<String>> {
+        match self.get_raw(name)? {
+            Some((REG_EXPAND_SZ, data)) if expand => {
+                Ok(Some(expand_environment_strings(&decode_str(&data)?)?))
+            }
+            Some((REG_SZ | REG_EXPAND_SZ, data)) => Ok(Some(decode_str(&data)?)),
+            Some(_) => Err(ERROR_DATATYPE_MISMATCH.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a string value as the exact UTF-16 stored, for paths that need not be valid UTF-16.
+    fn get_os_string(&self, name: PCWSTR) -> windows::core::Result<Option<OsString>> {
+        match self.get_raw(name)? {
+            Some((REG_SZ | REG_EXPAND_SZ, data)) => {
+                Ok(Some(OsString::from_wide(&decode_wide(&data))))
+            }
+            Some(_) => Err(ERROR_DATATYPE_MISMATCH.into()),
+            None => Ok(None),
+        }
+    }
+
+    fn subkeys(&self) -> windows::core::Result<Vec<String>> {
+        unsafe { enum_subkeys(self.raw_handle()) }
+    }
+
+    fn values(&self) -> windows::core::Result<Vec<String>> {
+        unsafe { enum_values(self.raw_handle()) }
+    }
+
+    /// Formats this key's values and subkeys recursively, for attaching to bug reports. Fails
+    /// instead of recursing forever if a symbolic link loops back into the tree.
+    fn dump(&self) -> windows::core::Result<String>
+    where
+        Self: Sized,
+    {
+        let mut output = String::new();
+        dump_into(self, 0, &mut output)?;
+        Ok(output)
+    }
+
+    fn last_write_time(&self) -> windows::core::Result<FILETIME> {
+        unsafe { query_last_write_time(self.raw_handle()) }
+    }
+
+    /// Like [`RegistryRead::last_write_time`], but as a [`SystemTime`].
+    fn last_write_system_time(&self) -> windows::core::Result<SystemTime> {
+        // The number of 100ns intervals between 1601-01-01 and 1970-01-01.
+        const UNIX_EPOCH_TICKS: u64 = 116_444_736_000_000_000;
+
+        let time = self.last_write_time()?;
+        let ticks = (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+        let since_epoch =
+            |ticks: u64| Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100);
+
+        if ticks >= UNIX_EPOCH_TICKS {
+            UNIX_EPOCH.checked_add(since_epoch(ticks - UNIX_EPOCH_TICKS))
+        } else {
+            UNIX_EPOCH.checked_sub(since_epoch(UNIX_EPOCH_TICKS - ticks))
+        }
+        .ok_or_else(|| ERROR_INVALID_DATA.into())
+    }
+
+    /// Returns whether this key was written after `time`.
+    fn write_time_newer_than(&self, time: FILETIME) -> windows::core::Result<bool> {
+        let ticks =
+            |time: FILETIME| (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+
+        Ok(ticks(self.last_write_time()?) > ticks(time))
+    }
+}
+
+fn dump_into<R: RegistryRead>(
+    key: &R,
+    depth: usize,
+    output: &mut String,
+) -> windows::core::Result<()> {
+    use std::fmt::Write;
+
+    check_depth(depth)?;
+
+    let indent = "    ".repeat(depth);
+
+    for name in key.values()? {
+        if let Some((value_type, data)) = key.get_raw(PCWSTR::from_raw(to_wide(&name).as_ptr()))? {
+            let name = if name.is_empty() { "(Default)" } else { &name };
+            let _ = writeln!(
+                output,
+                "{indent}{name} = {}",
+                format_value(value_type, &data)
+            );
+        }
+    }
+
+    for name in key.subkeys()? {
+        let _ = writeln!(output, "{indent}[{name}]");
+
+        if let Some(subkey) = key.try_open_subkey(PCWSTR::from_raw(to_wide(&name).as_ptr()))? {
+            dump_into(&subkey, depth + 1, output)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn format_value(value_type: REG_VALUE_TYPE, data: &[u8]) -> String {
+    const MAX_HEX_BYTES: usize = 32;
+
+    match value_type {
+        REG_SZ | REG_EXPAND_SZ => {
+            let name = if value_type == REG_SZ {
+                "REG_SZ"
+            } else {
+                "REG_EXPAND_SZ"
+            };
+
+            format!("{name} {:?}", String::from_utf16_lossy(&decode_wide(data)))
+        }
+        REG_DWORD if data.len() == 4 => {
+            let value = u32::from_le_bytes(data.try_into().unwrap());
+            format!("REG_DWORD {value:#010x} ({value})")
+        }
+        REG_QWORD if data.len() == 8 => {
+            let value = u64::from_le_bytes(data.try_into().unwrap());
+            format!("REG_QWORD {value:#018x} ({value})")
+        }
+        _ => {
+            let hex = data
+                .iter()
+                .take(MAX_HEX_BYTES)
+                .map(|b| format!("{b:02x}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ellipsis = if data.len() > MAX_HEX_BYTES {
+                " ..."
+            } else {
+                ""
+            };
+
+            format!(
+                "type {} {hex}{ellipsis} ({} bytes)",
+                value_type.0,
+                data.len()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{Win32::System::Registry::HKEY_CURRENT_USER, core::w};
+
+    use super::*;
+
+    fn ticks(time: FILETIME) -> u64 {
+        (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime)
+    }
+
+    fn filetime(ticks: u64) -> FILETIME {
+        FILETIME {
+            dwLowDateTime: ticks as u32,
+            dwHighDateTime: (ticks >> 32) as u32,
+        }
+    }
+
+    #[test]
+    fn write_time_newer_than_compares_whole_filetime() {
+        let key = ReadKey::open(HKEY_CURRENT_USER, w!("Software")).unwrap();
+        let time = key.last_write_time().unwrap();
+
+        assert!(
+            key.write_time_newer_than(filetime(ticks(time) - 1))
+                .unwrap()
+        );
+        assert!(!key.write_time_newer_than(time).unwrap());
+        assert!(
+            !key.write_time_newer_than(filetime(ticks(time) + 1))
+                .unwrap()
+        );
+
+        // The high DWORD decides the order regardless of the low one.
+        assert!(
+            key.write_time_newer_than(FILETIME {
+                dwLowDateTime: u32::MAX,
+                dwHighDateTime: time.dwHighDateTime - 1,
+            })
+            .unwrap()
+        );
+        assert!(
+            !key.write_time_newer_than(FILETIME {
+                dwLowDateTime: 0,
+                dwHighDateTime: time.dwHighDateTime + 1,
+            })
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn last_write_system_time_matches_filetime() {
+        const UNIX_EPOCH_TICKS: u64 = 116_444_736_000_000_000;
+
+        let key = ReadKey::open(HKEY_CURRENT_USER, w!("Software")).unwrap();
+        let time = ticks(key.last_write_time().unwrap());
+        let system_time = key.last_write_system_time().unwrap();
+
+        assert_eq!(
+            system_time,
+            UNIX_EPOCH + Duration::from_nanos((time - UNIX_EPOCH_TICKS) * 100)
+        );
+        assert!(system_time <= SystemTime::now());
+    }
+}
@@ -0,0 +1,29 @@
+use windows::{
+    Win32::{
+        Foundation::WIN32_ERROR,
+        System::Registry::{HKEY, RegConnectRegistryExW},
+    },
+    core::{Owned, PCWSTR},
+};
+
+use super::{PredefinedKey, direct::DirectKey};
+
+/// A key in another machine's registry. KTM transactions do not span machines, so it is
+/// non-transacted.
+pub type RemoteKey = DirectKey<'static>;
+
+/// Connects to `key` on `machine`, given as `\\name` or `name`.
+///
+/// `RegConnectRegistry` only reaches `HKEY_LOCAL_MACHINE` and `HKEY_USERS` (and
+/// `HKEY_PERFORMANCE_DATA`, which is of no use here); the merged `HKEY_CLASSES_ROOT` view has to
+/// be addressed as `HKEY_LOCAL_MACHINE\Software\Classes`. The caller needs to be an administrator
+/// on the remote machine, which has to be running the Remote Registry service.
+pub fn connect_remote(machine: PCWSTR, key: PredefinedKey) -> windows::core::Result<RemoteKey> {
+    let mut result = HKEY::default();
+
+    unsafe {
+        WIN32_ERROR(RegConnectRegistryExW(machine, key.into(), 0, &raw mut result) as u32).ok()?;
+    }
+
+    Ok(RemoteKey::new(unsafe { Owned::new(result) }))
+}
@@ -0,0 +1,134 @@
+use std::{ffi::OsStr, os::windows::ffi::OsStrExt, path::Path};
+
+use windows::{
+    Win32::System::Registry::{
+        REG_BINARY, REG_DWORD, REG_EXPAND_SZ, REG_MULTI_SZ, REG_NONE, REG_QWORD, REG_SZ,
+        REG_VALUE_TYPE,
+    },
+    core::{GUID, PCWSTR},
+};
+
+use super::{
+    raw::{pcwstr_with_nul, to_wide},
+    read::RegistryRead,
+};
+use crate::com::GuidExt;
+
+/// Write accessors shared by transacted [`Key`](super::transaction::Key)s and non-transacted
+/// [`DirectKey`](super::direct::DirectKey)s, so the registration helpers can also write to
+/// remote registries and loaded hives.
+///
+/// Strings are written nul-terminated, as `RegSetValueExW` expects for `REG_SZ` data.
+pub trait RegistryWrite: RegistryRead {
+    /// Creates `sub_key`, or opens it if it already exists.
+    fn create_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<Self>
+    where
+        Self: Sized;
+
+    /// Deletes `sub_key` and its subtree, treating a missing key as success. A null `sub_key`
+    /// empties this key instead.
+    fn delete_subkey(&self, sub_key: PCWSTR) -> windows::core::Result<()>;
+
+    fn set_raw(
+        &self,
+        name: PCWSTR,
+        value_type: REG_VALUE_TYPE,
+        data: &[u8],
+    ) -> windows::core::Result<()>;
+
+    /// Deletes the value `name`, treating a missing value as success.
+    fn delete_value(&self, name: PCWSTR) -> windows::core::Result<()>;
+
+    fn set_u32(&self, name: PCWSTR, value: u32) -> windows::core::Result<()> {
+        self.set_raw(name, REG_DWORD, &value.to_le_bytes())
+    }
+
+    fn set_u64(&self, name: PCWSTR, value: u64) -> windows::core::Result<()> {
+        self.set_raw(name, REG_QWORD, &value.to_le_bytes())
+    }
+
+    /// Writes a `REG_NONE` value without data, for values that only mark something by existing.
+    fn set_none(&self, name: PCWSTR) -> windows::core::Result<()> {
+        self.set_raw(name, REG_NONE, &[])
+    }
+
+    fn set_binary(&self, name: PCWSTR, value: &[u8]) -> windows::core::Result<()> {
+        self.set_raw(name, REG_BINARY, value)
+    }
+
+    fn set_str(&self, name: PCWSTR, value: &str) -> windows::core::Result<()> {
+        self.set_raw(name, REG_SZ, &wide_bytes(&to_wide(value)))
+    }
+
+    fn set_str_expand(&self, name: PCWSTR, value: &str) -> windows::core::Result<()> {
+        self.set_raw(name, REG_EXPAND_SZ, &wide_bytes(&to_wide(value)))
+    }
+
+    /// Writes `value` as a `REG_SZ` without going through `str`, preserving unpaired surrogates.
+    fn set_os_str(&self, name: PCWSTR, value: &OsStr) -> windows::core::Result<()> {
+        let value = value
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect::<Vec<_>>();
+
+        self.set_raw(name, REG_SZ, &wide_bytes(&value))
+    }
+
+    fn set_path(&self, name: PCWSTR, value: &Path) -> windows::core::Result<()> {
+        self.set_os_str(name, value.as_os_str())
+    }
+
+    /// Writes a `REG_MULTI_SZ`, terminating each string and the list itself with a nul.
+    fn set_multi_sz(&self, name: PCWSTR, values: &[&str]) -> windows::core::Result<()> {
+        let mut data = values
+            .iter()
+            .flat_map(|value| value.encode_utf16().chain(std::iter::once(0)))
+            .collect::<Vec<_>>();
+
+        // An empty list still needs both terminators to be a valid multi-string.
+        if values.is_empty() {
+            data.push(0);
+        }
+
+        data.push(0);
+        self.set_raw(name, REG_MULTI_SZ, &wide_bytes(&data))
+    }
+
+    /// Writes `value` as a `REG_SZ`, with a null `value` writing an empty string.
+    fn set_pcwstr(&self, name: PCWSTR, value: PCWSTR) -> windows::core::Result<()> {
+        self.set_raw(
+            name,
+            REG_SZ,
+            &wide_bytes(unsafe { pcwstr_with_nul(&value) }),
+        )
+    }
+
+    fn set_pcwstr_expand(&self, name: PCWSTR, value: PCWSTR) -> windows::core::Result<()> {
+        self.set_raw(
+            name,
+            REG_EXPAND_SZ,
+            &wide_bytes(unsafe { pcwstr_with_nul(&value) }),
+        )
+    }
+
+    fn set_guid(&self, name: PCWSTR, value: &GUID) -> windows::core::Result<()> {
+        self.set_raw(name, REG_SZ, &wide_bytes(&value.to_wide()))
+    }
+
+    /// Sets the key's unnamed default value.
+    fn set_default_str(&self, value: &str) -> windows::core::Result<()> {
+        self.set_str(PCWSTR::null(), value)
+    }
+
+    fn set_default_pcwstr(&self, value: PCWSTR) -> windows::core::Result<()> {
+        self.set_pcwstr(PCWSTR::null(), value)
+    }
+
+    fn set_default_guid(&self, value: &GUID) -> windows::core::Result<()> {
+        self.set_guid(PCWSTR::null(), value)
+    }
+}
+
+fn wide_bytes(value: &[u16]) -> Vec<u8> {
+    value.iter().flat_map(|c| c.to_le_bytes()).collect()
+}